@@ -49,18 +49,30 @@ async fn main() -> Result<()> {
         .temperature(0.5)
         .build();
 
-    // Send the completion request to the API
-    let completion_response = client
+    // Send the completion request to the API and keep the data of every model
+    let completion_data = client
         .completion()
         .bearer_auth(&api_key)
         .json(completion_request)
         .send()
-        .await?;
+        .await?
+        .get_completion_data()?;
 
-    // Print the response
+    // Print each model's answer along with its price and word counts
+    println!("\n\nMulti-model Completion Response:");
+    for (model, result) in completion_data.completions() {
+        println!(
+            "\n{} ({} coins, {} words):\n{:#?}",
+            model,
+            result.price().total(),
+            result.words().total(),
+            result.completion().choices
+        );
+    }
     println!(
-        "\n\nMulti-model Completion Response:\n\n{:#?}",
-        completion_response
+        "\nOverall: {} coins, {} words",
+        completion_data.overall_price().total(),
+        completion_data.overall_words().total()
     );
 
     Ok(())
@@ -1,6 +1,8 @@
 use anyhow::Result;
 #[cfg(feature = "file")]
 use straico::client::StraicoClient;
+#[cfg(feature = "file")]
+use straico::endpoints::file::FileRequest;
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -12,15 +14,12 @@ async fn main() -> Result<()> {
     #[cfg(feature = "file")]
     let api_key = std::env::var("STRAICO_API_KEY").expect("STRAICO_API_KEY must be set");
 
-    // Create a file request using the builder pattern
-    // #[cfg(feature = "file")]
-    // let file_request = FileRequest::new().file("path/to/your/file.txt");
-
-    // Send the file request to the API
+    // Send a file from the local filesystem to the API
     #[cfg(feature = "file")]
     let file_response = client
+        .clone()
         .file()
-        .bearer_auth(api_key)
+        .bearer_auth(&api_key)
         .multipart("path/to/file.txt")
         .await?
         .send()
@@ -30,5 +29,47 @@ async fn main() -> Result<()> {
     #[cfg(feature = "file")]
     println!("File Upload Response:\n\n{:#?}", file_response);
 
+    // Create a file request from content held in memory, no temporary file is needed
+    #[cfg(feature = "file")]
+    let file_request = FileRequest::new()
+        .bytes(b"Hello from memory".to_vec())
+        .file_name("hello.txt")
+        .mime_type("text/plain")
+        .build();
+
+    // Send the in-memory file to the API
+    #[cfg(feature = "file")]
+    let file_response = client
+        .clone()
+        .file()
+        .bearer_auth(&api_key)
+        .upload(file_request)
+        .await?
+        .send()
+        .await?;
+
+    #[cfg(feature = "file")]
+    println!("\n\nIn-memory Upload Response:\n\n{:#?}", file_response);
+
+    // Create a file request from any AsyncRead, the content is streamed while sending
+    #[cfg(feature = "file")]
+    let file_request = FileRequest::new()
+        .reader(tokio::fs::File::open("path/to/file.txt").await?)
+        .file_name("file.txt")
+        .build();
+
+    // Send the streamed file to the API
+    #[cfg(feature = "file")]
+    let file_response = client
+        .file()
+        .bearer_auth(&api_key)
+        .upload(file_request)
+        .await?
+        .send()
+        .await?;
+
+    #[cfg(feature = "file")]
+    println!("\n\nStreamed Upload Response:\n\n{:#?}", file_response);
+
     Ok(())
 }
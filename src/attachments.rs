@@ -0,0 +1,292 @@
+use crate::proxy_error::ProxyError;
+use crate::AppState;
+use base64::engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD as BASE64_URL};
+use base64::Engine;
+use regex::Regex;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::sync::{LazyLock, Mutex};
+use std::time::Instant;
+use straico::chat::Chat;
+use straico::endpoints::completion::completion_response::{ContentPart, Message};
+
+/// Prefix of the file identifiers issued by `/v1/files`
+const FILE_ID_PREFIX: &str = "file-";
+
+/// Number of uploads remembered by `UploadCache`
+const MAX_CACHED_UPLOADS: usize = 1024;
+
+/// Matches links to YouTube videos, in their long, short, shorts and embed forms
+static YOUTUBE_URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"https?://(?:(?:www|m)\.)?(?:youtube\.com/(?:watch\?\S*?v=|shorts/|embed/)|youtu\.be/)(?<id>[\w-]{11})",
+    )
+    .unwrap()
+});
+
+/// Builds the OpenAI file identifier for a file uploaded to Straico
+///
+/// The identifier encodes the Straico file URL, so it can be resolved back to the URL
+/// without keeping any state in the proxy.
+pub fn file_id(url: &str) -> String {
+    format!("{}{}", FILE_ID_PREFIX, BASE64_URL.encode(url))
+}
+
+/// Resolves an OpenAI file identifier issued by `file_id` back to the Straico file URL
+///
+/// # Returns
+/// The file URL, or `None` if the identifier was not issued by this proxy
+pub fn file_url(id: &str) -> Option<String> {
+    let encoded = id.strip_prefix(FILE_ID_PREFIX)?;
+    let url = String::from_utf8(BASE64_URL.decode(encoded).ok()?).ok()?;
+    url.starts_with("http").then_some(url)
+}
+
+/// Returns the canonical watch URL if `url` points to a YouTube video
+fn youtube_url(url: &str) -> Option<String> {
+    YOUTUBE_URL
+        .captures(url)
+        .filter(|captures| captures.get(0).is_some_and(|m| m.start() == 0))
+        .map(|captures| format!("https://www.youtube.com/watch?v={}", &captures["id"]))
+}
+
+/// The MIME type, if given, and the decoded content of a `data:` URL
+type DataUrl<'a> = (Option<&'a str>, Vec<u8>);
+
+/// Splits a base64 `data:` URL into its MIME type and decoded content
+///
+/// # Returns
+/// The MIME type, if given, and the content; `None` if `url` is not a `data:` URL
+///
+/// # Errors
+/// A bad request error if the URL is not base64 encoded or the content is invalid
+fn decode_data_url(url: &str) -> Option<Result<DataUrl<'_>, ProxyError>> {
+    let (header, payload) = url.strip_prefix("data:")?.split_once(',')?;
+    let Some(media_type) = header.strip_suffix(";base64") else {
+        return Some(Err(ProxyError::invalid_request(
+            "only base64 encoded data URLs are supported",
+        )));
+    };
+    let mime_type = media_type.split(';').next().filter(|m| !m.is_empty());
+    Some(
+        BASE64
+            .decode(payload.trim())
+            .map(|bytes| (mime_type, bytes))
+            .map_err(ProxyError::invalid_request),
+    )
+}
+
+/// Returns the usual file extension for a MIME type, used to name inline uploads
+fn extension(mime_type: &str) -> &str {
+    match mime_type {
+        "text/plain" => "txt",
+        "image/jpeg" => "jpg",
+        "audio/mpeg" => "mp3",
+        "text/javascript" | "application/javascript" => "js",
+        "text/x-python" => "py",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => "docx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => "xlsx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation" => "pptx",
+        _ => mime_type
+            .rsplit('/')
+            .next()
+            .and_then(|subtype| subtype.split('+').next())
+            .unwrap_or("bin"),
+    }
+}
+
+/// Files and videos attached to the messages of a chat completion request
+///
+/// # Fields
+/// * `file_urls` - Straico file URLs and remote files, sent as `file_urls`
+/// * `youtube_urls` - YouTube links, sent as `youtube_urls`
+#[derive(Default, Debug)]
+pub struct Attachments {
+    pub file_urls: Vec<String>,
+    pub youtube_urls: Vec<String>,
+}
+
+impl Attachments {
+    /// Collects the attachments of the user messages of a chat
+    ///
+    /// `image_url` and `file` parts are resolved to URLs: inline `data:` URLs and base64 file
+    /// data are uploaded to Straico, file identifiers issued by `/v1/files` are decoded and
+    /// remote URLs are kept as they are. Inline files are uploaded once, see `UploadCache`,
+    /// so the earlier turns of a conversation are not uploaded again with each new turn.
+    /// Parts linking to YouTube are routed to `youtube_urls`, while links written in the
+    /// text are left to the model. Duplicates are dropped, and parts of other types, such
+    /// as input audio, are skipped with a warning.
+    ///
+    /// # Arguments
+    /// * `data` - Shared application state containing client and configuration
+    /// * `key` - The Straico API key to upload inline files with
+    /// * `chat` - The messages of the request
+    ///
+    /// # Returns
+    /// * `Result<Attachments, ProxyError>` - The attachments, or a bad request error for invalid parts
+    pub async fn collect(data: &AppState, key: &str, chat: &Chat) -> Result<Self, ProxyError> {
+        let mut attachments = Attachments::default();
+        for message in chat.iter() {
+            let Message::User { content } = message else {
+                continue;
+            };
+            for part in content.parts() {
+                match part {
+                    ContentPart::Text { .. } => {}
+                    ContentPart::Unsupported => {
+                        eprintln!("warning: ignoring a content part of an unsupported type");
+                    }
+                    ContentPart::ImageUrl { image_url } => {
+                        let url = match decode_data_url(&image_url.url) {
+                            Some(decoded) => {
+                                let (mime_type, bytes) = decoded?;
+                                upload(data, key, bytes, None, mime_type).await?
+                            }
+                            None => image_url.url.to_string(),
+                        };
+                        attachments.push(url);
+                    }
+                    ContentPart::File { file } => {
+                        let url = match (&file.file_id, &file.file_data) {
+                            (Some(id), _) => file_url(id).ok_or_else(|| {
+                                ProxyError::invalid_request(format!("unknown file id '{}'", id))
+                            })?,
+                            (None, Some(file_data)) => {
+                                let (mime_type, bytes) = match decode_data_url(file_data) {
+                                    Some(decoded) => decoded?,
+                                    None => (
+                                        None,
+                                        BASE64
+                                            .decode(file_data.trim())
+                                            .map_err(ProxyError::invalid_request)?,
+                                    ),
+                                };
+                                upload(data, key, bytes, file.filename.as_deref(), mime_type)
+                                    .await?
+                            }
+                            (None, None) => {
+                                return Err(ProxyError::invalid_request(
+                                    "file parts require either 'file_id' or 'file_data'",
+                                ))
+                            }
+                        };
+                        attachments.push(url);
+                    }
+                }
+            }
+        }
+        Ok(attachments)
+    }
+
+    /// Adds a URL to `youtube_urls` or `file_urls` depending on where it points
+    ///
+    /// YouTube links are normalized so the same video is only attached once.
+    fn push(&mut self, url: String) {
+        let (urls, url) = match youtube_url(&url) {
+            Some(url) => (&mut self.youtube_urls, url),
+            None => (&mut self.file_urls, url),
+        };
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+}
+
+/// Remembers the Straico URLs of the inline files uploaded for each API key
+///
+/// Chat requests repeat the whole conversation, so without the cache every inline file of
+/// earlier turns would be uploaded, and paid for, again with each turn. Files are told
+/// apart by a hash of their name, type and content, keyed randomly for each run so that
+/// collisions cannot be crafted. The least recently used uploads are forgotten first.
+pub struct UploadCache {
+    /// Hashes the uploaded files
+    hasher: RandomState,
+    /// The URL of each upload and when it was last used, by API key and file hash
+    entries: Mutex<HashMap<(String, u64), (Instant, String)>>,
+}
+
+impl UploadCache {
+    /// Creates an empty cache
+    pub fn new() -> Self {
+        UploadCache {
+            hasher: RandomState::new(),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the hash identifying a file
+    fn hash(&self, bytes: &[u8], filename: &str, mime_type: Option<&str>) -> u64 {
+        self.hasher.hash_one((bytes, filename, mime_type))
+    }
+
+    /// Returns the URL of a file uploaded with a key, if it was
+    fn get(&self, key: &str, hash: u64) -> Option<String> {
+        let mut entries = self.entries.lock().ok()?;
+        let (used, url) = entries.get_mut(&(key.to_string(), hash))?;
+        *used = Instant::now();
+        Some(url.clone())
+    }
+
+    /// Stores the URL of a file uploaded with a key, forgetting the least recently used
+    /// upload when the cache is full
+    fn set(&self, key: &str, hash: u64, url: &str) {
+        let Ok(mut entries) = self.entries.lock() else {
+            return;
+        };
+        if entries.len() >= MAX_CACHED_UPLOADS {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (used, _))| *used)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert((key.to_string(), hash), (Instant::now(), url.to_string()));
+    }
+}
+
+/// Uploads inline file content to Straico and returns the URL of the uploaded file
+///
+/// Files already uploaded with the same key are not uploaded again, see `UploadCache`.
+///
+/// # Arguments
+/// * `data` - Shared application state containing client and configuration
+/// * `key` - The Straico API key to upload with
+/// * `bytes` - The decoded file content
+/// * `filename` - Optional file name, derived from the MIME type if missing
+/// * `mime_type` - Optional MIME type of the content
+///
+/// # Returns
+/// * `Result<String, ProxyError>` - The Straico file URL or error
+async fn upload(
+    data: &AppState,
+    key: &str,
+    bytes: Vec<u8>,
+    filename: Option<&str>,
+    mime_type: Option<&str>,
+) -> Result<String, ProxyError> {
+    let filename = match filename {
+        Some(filename) => filename.to_string(),
+        None => format!("upload.{}", mime_type.map_or("bin", extension)),
+    };
+    let hash = data.uploads.hash(&bytes, &filename, mime_type);
+    if let Some(url) = data.uploads.get(key, hash) {
+        return Ok(url);
+    }
+    if data.debug {
+        eprintln!("\n\n===== Uploading inline attachment: =====");
+        eprintln!("\n{} ({} bytes, {:?})", filename, bytes.len(), mime_type);
+    }
+    let file_data = data
+        .client
+        .clone()
+        .file()
+        .bearer_auth(key)
+        .multipart_bytes(bytes, &filename, mime_type)?
+        .send()
+        .await?
+        .get_file()?;
+    data.uploads.set(key, hash, &file_data.url);
+    Ok(file_data.url)
+}
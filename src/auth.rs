@@ -0,0 +1,312 @@
+use crate::proxy_error::ProxyError;
+use crate::AppState;
+use actix_web::dev::Payload;
+use actix_web::http::header::AUTHORIZATION;
+use actix_web::{web, FromRequest, HttpRequest};
+use clap::ValueEnum;
+use futures::future::{ready, Ready};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::path::Path;
+use straico::error::StraicoError;
+use subtle::ConstantTimeEq;
+
+/// How callers of the proxy authenticate and which Straico key their requests use
+///
+/// # Variants
+/// * `Static` - Every request uses the key given with `--api-key`, callers are not checked
+/// * `Passthrough` - The caller's bearer token is forwarded as the Straico key
+/// * `Keys` - The caller's bearer token must be a key issued in the keys file
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum AuthMode {
+    Static,
+    Passthrough,
+    Keys,
+}
+
+/// The authentication configured for the proxy
+///
+/// # Variants
+/// * `Static` - Holds the Straico key used for every request
+/// * `Passthrough` - Forwards the caller's bearer token
+/// * `Keys` - Holds the issued keys along with the callers they were issued to
+pub enum Auth {
+    Static(String),
+    Passthrough,
+    Keys(Vec<IssuedKey>),
+}
+
+/// A key issued to a caller of the proxy
+///
+/// # Fields
+/// * `key` - The key the caller authenticates with
+/// * `digest` - The SHA-256 digest of the key, compared in constant time with the digest
+///   of the caller's bearer token
+/// * `name` - Optional name of the caller, used in debug logs
+/// * `upstream_key` - The Straico key used for the caller's requests
+/// * `daily_budget` - Optional coins the caller may spend per day, overriding
+///   `--key-daily-budget`
+pub struct IssuedKey {
+    key: String,
+    digest: [u8; 32],
+    name: Option<String>,
+    upstream_key: String,
+    daily_budget: Option<f32>,
+}
+
+/// The content of a keys file
+///
+/// ```toml
+/// [[keys]]
+/// key = "sk-proxy-alice"
+/// name = "alice"
+/// upstream_key = "straico-key-of-alice"
+///
+/// [[keys]]
+/// key = "sk-proxy-bob"
+/// name = "bob"
+/// daily_budget = 50.0
+/// # uses the key given with --api-key
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct KeysFile {
+    keys: Vec<KeyEntry>,
+}
+
+/// An entry of a keys file
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct KeyEntry {
+    key: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    upstream_key: Option<String>,
+    #[serde(default)]
+    daily_budget: Option<f32>,
+}
+
+impl Auth {
+    /// Loads the keys issued to callers from a keys file
+    ///
+    /// Files ending in `.toml` are read as TOML, any other file as JSON.
+    ///
+    /// # Arguments
+    /// * `path` - The path of the keys file
+    /// * `default_upstream_key` - The Straico key for entries without `upstream_key`
+    ///
+    /// # Returns
+    /// The authentication, `StraicoError::Io` if the file cannot be read, or
+    /// `StraicoError::Config` if it is malformed, issues a key twice or leaves an entry
+    /// without a Straico key
+    pub fn load_keys<P: AsRef<Path>>(
+        path: P,
+        default_upstream_key: Option<&str>,
+    ) -> Result<Self, StraicoError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let file: KeysFile = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str(&content).map_err(|e| e.to_string())
+        }
+        .map_err(StraicoError::Config)?;
+
+        let mut keys = Vec::with_capacity(file.keys.len());
+        let mut seen = HashSet::with_capacity(file.keys.len());
+        for entry in file.keys {
+            let label = entry
+                .name
+                .clone()
+                .unwrap_or_else(|| String::from("unnamed"));
+            let upstream_key = entry
+                .upstream_key
+                .or_else(|| default_upstream_key.map(str::to_string))
+                .ok_or_else(|| {
+                    StraicoError::Config(format!(
+                        "key of '{}' has no upstream_key and no --api-key is set",
+                        label
+                    ))
+                })?;
+            let issued = IssuedKey::new(entry.key, entry.name, upstream_key, entry.daily_budget);
+            if !seen.insert(issued.digest) {
+                return Err(StraicoError::Config(format!(
+                    "the key of '{}' is issued more than once",
+                    label
+                )));
+            }
+            keys.push(issued);
+        }
+        Ok(Auth::Keys(keys))
+    }
+
+    /// Returns the daily budgets set for issued keys in the keys file
+    pub fn key_budgets(&self) -> impl Iterator<Item = (&str, f32)> {
+        let keys = match self {
+            Auth::Keys(keys) => Some(keys),
+            _ => None,
+        };
+        keys.into_iter().flatten().filter_map(|issued| {
+            issued
+                .daily_budget
+                .map(|budget| (issued.key.as_str(), budget))
+        })
+    }
+
+    /// Returns the Straico key to use for a request, along with the caller
+    ///
+    /// # Arguments
+    /// * `req` - The incoming request
+    /// * `debug` - Whether to log the caller
+    ///
+    /// # Returns
+    /// The key, or an authentication error if the caller's bearer token is missing or,
+    /// with issued keys, unknown
+    fn api_key(&self, req: &HttpRequest, debug: bool) -> Result<ApiKey, ProxyError> {
+        match self {
+            Auth::Static(key) => Ok(ApiKey::new(key, key)),
+            Auth::Passthrough => bearer_token(req)
+                .map(|token| ApiKey::new(token, token))
+                .ok_or_else(missing_token),
+            Auth::Keys(keys) => {
+                let token = bearer_token(req).ok_or_else(missing_token)?;
+                // Every issued key is compared so the time taken does not depend on the match
+                let digest = sha256(token);
+                let issued = keys
+                    .iter()
+                    .fold(None, |found, issued| {
+                        if bool::from(issued.digest.ct_eq(&digest)) {
+                            Some(issued)
+                        } else {
+                            found
+                        }
+                    })
+                    .ok_or_else(|| ProxyError::unauthorized("invalid API key"))?;
+                if debug {
+                    let name = issued.name.as_deref().unwrap_or("unnamed");
+                    eprintln!("\n\n===== Request authenticated as {} =====", name);
+                }
+                Ok(ApiKey::new(&issued.upstream_key, token))
+            }
+        }
+    }
+}
+
+impl IssuedKey {
+    /// Creates an issued key, computing the digest it is looked up by
+    fn new(
+        key: String,
+        name: Option<String>,
+        upstream_key: String,
+        daily_budget: Option<f32>,
+    ) -> Self {
+        IssuedKey {
+            digest: sha256(&key),
+            key,
+            name,
+            upstream_key,
+            daily_budget,
+        }
+    }
+}
+
+/// Returns the SHA-256 digest of a key
+fn sha256(key: &str) -> [u8; 32] {
+    let mut digest = [0; 32];
+    digest.copy_from_slice(ring::digest::digest(&ring::digest::SHA256, key.as_bytes()).as_ref());
+    digest
+}
+
+/// Returns the token of a `Authorization: Bearer` header, if any
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    let token = token.trim();
+    (scheme.eq_ignore_ascii_case("bearer") && !token.is_empty()).then_some(token)
+}
+
+/// Builds the error returned when a request carries no bearer token
+fn missing_token() -> ProxyError {
+    ProxyError::unauthorized("missing API key, send it as 'Authorization: Bearer <key>'")
+}
+
+/// The Straico API key to use for a request, extracted according to the configured `Auth`
+///
+/// Handlers taking an `ApiKey` reject unauthenticated requests with a 401 error.
+///
+/// # Fields
+/// * `key` - The Straico API key
+/// * `caller` - The key the caller authenticated with, which budgets are counted for: the
+///   issued key, or the Straico key itself without issued keys
+pub struct ApiKey {
+    key: String,
+    caller: String,
+}
+
+impl ApiKey {
+    /// Creates the key of a request
+    pub(crate) fn new(key: &str, caller: &str) -> Self {
+        ApiKey {
+            key: key.to_string(),
+            caller: caller.to_string(),
+        }
+    }
+
+    /// Returns the key the caller authenticated with
+    pub fn caller(&self) -> &str {
+        &self.caller
+    }
+}
+
+impl AsRef<str> for ApiKey {
+    fn as_ref(&self) -> &str {
+        &self.key
+    }
+}
+
+impl FromRequest for ApiKey {
+    type Error = ProxyError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let key = match req.app_data::<web::Data<AppState>>() {
+            Some(data) => data.auth.api_key(req, data.debug),
+            None => Err(StraicoError::Config(String::from("application state is missing")).into()),
+        };
+        ready(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::StatusCode;
+    use actix_web::test::TestRequest;
+    use actix_web::ResponseError;
+
+    #[test]
+    fn issued_keys() {
+        let auth = Auth::Keys(vec![
+            IssuedKey::new("sk-a".into(), Some("alice".into()), "up-a".into(), None),
+            IssuedKey::new("sk-b".into(), None, "up-b".into(), Some(5.0)),
+        ]);
+        let cases = [
+            (Some("Bearer sk-a"), Ok("up-a")),
+            (Some("bearer sk-b"), Ok("up-b")),
+            (Some("Bearer sk-c"), Err(StatusCode::UNAUTHORIZED)),
+            (Some("Bearer sk-a "), Ok("up-a")),
+            (Some("Bearer sk-"), Err(StatusCode::UNAUTHORIZED)),
+            (None, Err(StatusCode::UNAUTHORIZED)),
+        ];
+        for (header, expected) in cases {
+            let mut req = TestRequest::default();
+            if let Some(header) = header {
+                req = req.insert_header((AUTHORIZATION, header));
+            }
+            let key = auth.api_key(&req.to_http_request(), false);
+            let key = key.as_ref().map(AsRef::as_ref).map_err(|e| e.status_code());
+            assert_eq!(key, expected, "{:?}", header);
+        }
+    }
+}
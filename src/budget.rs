@@ -0,0 +1,552 @@
+use crate::endpoints::completion::completion_request::CompletionRequest;
+#[cfg(feature = "image")]
+use crate::endpoints::image::{ImageRequest, ImageSize};
+#[cfg(feature = "image")]
+use crate::endpoints::model::ImagePricing;
+use crate::endpoints::model::ModelData;
+use crate::error::StraicoError;
+use reqwest::StatusCode;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many words a token stands for on average, used to convert `max_tokens`
+const WORDS_PER_TOKEN: f32 = 0.75;
+
+/// The coin prices of the models, taken from the model listing
+///
+/// Chat models are billed per word, prompt and answer together, and image models per image
+/// of a given format. Estimates are upper bounds: the answer is assumed to be as long as
+/// `max_tokens` allows, or as long as the model can answer when it is not set. Attached
+/// files and videos are not counted.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), straico::error::StraicoError> {
+/// use straico::budget::Pricing;
+/// use straico::client::StraicoClient;
+/// use straico::endpoints::completion::completion_request::CompletionRequest;
+///
+/// let models = StraicoClient::new()
+///     .models()
+///     .bearer_auth("api-key")
+///     .send()
+///     .await?
+///     .get_models()?;
+/// let pricing = Pricing::from_models(&models);
+/// let request = CompletionRequest::new()
+///     .models("openai/gpt-4o")
+///     .message("Hello")
+///     .max_tokens(500)
+///     .build();
+/// println!("at most {:.2} coins", pricing.estimate_completion(&request)?);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Pricing {
+    chat: HashMap<String, ChatRate>,
+    #[cfg(feature = "image")]
+    image: HashMap<String, ImagePricing>,
+}
+
+/// The price and output limit of a chat model
+#[derive(Clone, Copy, Debug)]
+struct ChatRate {
+    coins_per_word: f32,
+    max_output: u32,
+}
+
+impl Pricing {
+    /// Collects the prices of the chat and image models of a model listing
+    ///
+    /// # Arguments
+    /// * `models` - The models returned by the models endpoint
+    pub fn from_models(models: &ModelData) -> Self {
+        let chat = models
+            .chat()
+            .iter()
+            .map(|model| {
+                let pricing = model.pricing();
+                let rate = ChatRate {
+                    coins_per_word: pricing.coins() / f32::from(pricing.words().max(1)),
+                    max_output: model.max_output(),
+                };
+                (model.model().clone(), rate)
+            })
+            .collect();
+        Pricing {
+            chat,
+            #[cfg(feature = "image")]
+            image: models
+                .image()
+                .iter()
+                .map(|model| (model.model().clone(), model.pricing().clone()))
+                .collect(),
+        }
+    }
+
+    /// Estimates the most a completion request can cost
+    ///
+    /// Requests to several models are billed for each of them.
+    ///
+    /// # Arguments
+    /// * `request` - The completion request
+    ///
+    /// # Returns
+    /// The estimated coins, or `StraicoError::InvalidRequest` if the price of a model is
+    /// unknown
+    pub fn estimate_completion(&self, request: &CompletionRequest) -> Result<f32, StraicoError> {
+        let input = request.get_message().split_whitespace().count() as f32;
+        request.get_models().iter().try_fold(0.0, |total, model| {
+            let rate = self.chat.get(model).ok_or_else(|| unknown_model(model))?;
+            let tokens = request.get_max_tokens().copied().unwrap_or(rate.max_output);
+            let output = (tokens as f32 * WORDS_PER_TOKEN).ceil();
+            Ok(total + (input + output) * rate.coins_per_word)
+        })
+    }
+
+    /// Estimates the cost of an image generation request
+    ///
+    /// # Arguments
+    /// * `request` - The image request
+    ///
+    /// # Returns
+    /// The estimated coins, or `StraicoError::InvalidRequest` if the price of the model is
+    /// unknown or the size is not supported
+    #[cfg(feature = "image")]
+    pub fn estimate_image(&self, request: &ImageRequest) -> Result<f32, StraicoError> {
+        let pricing = self
+            .image
+            .get(request.model())
+            .ok_or_else(|| unknown_model(request.model()))?;
+        let size = match request.size().parse::<ImageSize>()? {
+            ImageSize::Square => pricing.square(),
+            ImageSize::Landscape => pricing.landscape(),
+            ImageSize::Portrait => pricing.portrait(),
+        };
+        Ok(f32::from(size.coins()) * f32::from(request.variations()))
+    }
+}
+
+/// Builds the error returned for a model without a known price
+fn unknown_model(model: &str) -> StraicoError {
+    StraicoError::InvalidRequest(format!("the price of model '{}' is unknown", model))
+}
+
+/// Spending limits checked before paid requests are sent
+///
+/// Each request is estimated with `Pricing` and refused with `StraicoError::BudgetExceeded`
+/// if it would cost more than the limit per request, or make the coins spent today exceed
+/// the daily limit, overall or of the key it is sent for. Days are counted in UTC. When a
+/// balance is given, requests that would leave less than the minimum balance are refused
+/// with `StraicoError::InsufficientCoins`.
+///
+/// A request to a single model that has cheaper models configured with `downgrade` is sent
+/// to the first of them that fits instead of being refused.
+///
+/// Approved requests reserve their estimate until they are settled with what they cost, so
+/// that requests running in parallel cannot overspend together. A request that failed ends
+/// its reservation with `fail`. A reservation dropped without being settled, e.g. because
+/// the request was cancelled while waiting for the response, counts its estimate as spent,
+/// as Straico may have charged the request.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), straico::error::StraicoError> {
+/// use straico::budget::{Budget, Pricing};
+/// use straico::client::StraicoClient;
+/// use straico::endpoints::completion::completion_request::CompletionRequest;
+///
+/// let client = StraicoClient::new();
+/// let models = client.clone().models().bearer_auth("api-key").send().await?.get_models()?;
+/// let pricing = Pricing::from_models(&models);
+/// let budget = Budget::new()
+///     .max_per_request(20.0)
+///     .max_per_day(500.0)
+///     .downgrade("openai/gpt-4o", ["openai/gpt-4o-mini"]);
+///
+/// let mut request = CompletionRequest::new()
+///     .models("openai/gpt-4o")
+///     .message("Hello")
+///     .build();
+/// let reservation = budget.approve_completion(&pricing, "api-key", None, &mut request)?;
+/// let result = client
+///     .completion()
+///     .bearer_auth("api-key")
+///     .json(request)
+///     .send()
+///     .await
+///     .and_then(|response| response.get_completion_data());
+/// match &result {
+///     Ok(data) => reservation.settle(data.overall_price().total()),
+///     Err(error) => reservation.fail(error),
+/// }
+/// let data = result?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct Budget {
+    max_per_request: Option<f32>,
+    max_per_day: Option<f32>,
+    max_per_key: Option<f32>,
+    key_limits: HashMap<String, f32>,
+    min_balance: f32,
+    downgrades: HashMap<String, Vec<String>>,
+    spending: Mutex<Spending>,
+}
+
+/// The coins spent and reserved during a day
+#[derive(Debug, Default)]
+struct Spending {
+    day: u64,
+    total: f32,
+    keys: HashMap<String, f32>,
+}
+
+/// Coins set aside for an approved request, see `Budget`
+///
+/// Settle it with the price of the response once the request succeeded, or end it with
+/// `fail` if the request failed. Dropped otherwise, the estimate counts as spent.
+#[derive(Debug)]
+pub struct Reservation<'b> {
+    budget: &'b Budget,
+    key: String,
+    day: u64,
+    coins: f32,
+    cost: Option<f32>,
+    downgraded_from: Option<String>,
+}
+
+impl Spending {
+    /// Starts a new day if the current one is over
+    fn roll(&mut self, day: u64) {
+        if self.day != day {
+            *self = Spending {
+                day,
+                ..Spending::default()
+            };
+        }
+    }
+}
+
+/// Whether a status code means the API refused a request without charging it
+fn refused(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 400 | 401 | 402 | 403 | 429)
+}
+
+/// Returns the number of the current UTC day
+fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() / 86400)
+}
+
+impl Budget {
+    /// Creates a budget without limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the most a single request may cost
+    pub fn max_per_request(mut self, coins: f32) -> Self {
+        self.max_per_request = Some(coins);
+        self
+    }
+
+    /// Sets the most all requests together may cost per day
+    pub fn max_per_day(mut self, coins: f32) -> Self {
+        self.max_per_day = Some(coins);
+        self
+    }
+
+    /// Sets the most the requests of each key may cost per day
+    pub fn max_per_key(mut self, coins: f32) -> Self {
+        self.max_per_key = Some(coins);
+        self
+    }
+
+    /// Sets the most the requests of one key may cost per day, overriding `max_per_key`
+    ///
+    /// # Arguments
+    /// * `key` - The key, as given to `approve_completion` and `approve_image`
+    /// * `coins` - The daily limit of the key
+    pub fn key_limit(mut self, key: impl Into<String>, coins: f32) -> Self {
+        self.key_limits.insert(key.into(), coins);
+        self
+    }
+
+    /// Sets how many coins must remain on the account after a request, when the balance is
+    /// known
+    pub fn min_balance(mut self, coins: f32) -> Self {
+        self.min_balance = coins;
+        self
+    }
+
+    /// Sets cheaper models to send the requests of a model to when they exceed the budget
+    ///
+    /// # Arguments
+    /// * `model` - The model requested
+    /// * `cheaper` - The models to try instead, in order of preference
+    pub fn downgrade<I, S>(mut self, model: impl Into<String>, cheaper: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let cheaper = cheaper.into_iter().map(Into::into).collect();
+        self.downgrades.insert(model.into(), cheaper);
+        self
+    }
+
+    /// Returns the coins spent and reserved today by all requests
+    pub fn spent_today(&self) -> f32 {
+        let mut spending = self.lock();
+        spending.roll(today());
+        spending.total
+    }
+
+    /// Returns the coins spent and reserved today by the requests of a key
+    pub fn spent_today_by(&self, key: &str) -> f32 {
+        let mut spending = self.lock();
+        spending.roll(today());
+        spending.keys.get(key).copied().unwrap_or_default()
+    }
+
+    /// Checks a completion request against the budget and reserves its estimate
+    ///
+    /// A request exceeding the budget is sent to a cheaper model when one is configured for
+    /// its model and fits, in which case its models are replaced.
+    ///
+    /// # Arguments
+    /// * `pricing` - The prices of the models
+    /// * `key` - The key the spending is counted for, e.g. the API key or a user name
+    /// * `balance` - The coins on the account, if known
+    /// * `request` - The request, whose models may be replaced
+    ///
+    /// # Returns
+    /// The reservation, `StraicoError::BudgetExceeded` or `StraicoError::InsufficientCoins`
+    /// if the request is refused, or `StraicoError::InvalidRequest` if a price is unknown
+    pub fn approve_completion(
+        &self,
+        pricing: &Pricing,
+        key: &str,
+        balance: Option<f32>,
+        request: &mut CompletionRequest,
+    ) -> Result<Reservation<'_>, StraicoError> {
+        let refusal = match self.reserve(key, pricing.estimate_completion(request)?, balance) {
+            Ok(reservation) => return Ok(reservation),
+            Err(refusal) => refusal,
+        };
+        let models: Vec<String> = request.get_models().iter().map(String::from).collect();
+        let Ok([model]) = <[String; 1]>::try_from(models) else {
+            return Err(refusal);
+        };
+        for cheaper in self.downgrades.get(&model).into_iter().flatten() {
+            request.set_models(Cow::Owned(cheaper.clone()));
+            let estimate = pricing.estimate_completion(request)?;
+            if let Ok(reservation) = self.reserve(key, estimate, balance) {
+                return Ok(reservation.downgraded(model));
+            }
+        }
+        request.set_models(Cow::Owned(model));
+        Err(refusal)
+    }
+
+    /// Checks an image generation request against the budget and reserves its cost
+    ///
+    /// A request exceeding the budget is sent to a cheaper model when one is configured for
+    /// its model and fits, in which case its model is replaced.
+    ///
+    /// # Arguments
+    /// * `pricing` - The prices of the models
+    /// * `key` - The key the spending is counted for, e.g. the API key or a user name
+    /// * `balance` - The coins on the account, if known
+    /// * `request` - The request, whose model may be replaced
+    ///
+    /// # Returns
+    /// The reservation, `StraicoError::BudgetExceeded` or `StraicoError::InsufficientCoins`
+    /// if the request is refused, or `StraicoError::InvalidRequest` if a price is unknown
+    #[cfg(feature = "image")]
+    pub fn approve_image(
+        &self,
+        pricing: &Pricing,
+        key: &str,
+        balance: Option<f32>,
+        request: &mut ImageRequest,
+    ) -> Result<Reservation<'_>, StraicoError> {
+        let refusal = match self.reserve(key, pricing.estimate_image(request)?, balance) {
+            Ok(reservation) => return Ok(reservation),
+            Err(refusal) => refusal,
+        };
+        let model = request.model().clone();
+        for cheaper in self.downgrades.get(&model).into_iter().flatten() {
+            request.set_model(cheaper);
+            let estimate = pricing.estimate_image(request)?;
+            if let Ok(reservation) = self.reserve(key, estimate, balance) {
+                return Ok(reservation.downgraded(model));
+            }
+        }
+        request.set_model(&model);
+        Err(refusal)
+    }
+
+    /// Reserves coins for a request if it fits in every limit
+    fn reserve(
+        &self,
+        key: &str,
+        coins: f32,
+        balance: Option<f32>,
+    ) -> Result<Reservation<'_>, StraicoError> {
+        let exceeded = |message: String| {
+            Err(StraicoError::BudgetExceeded(format!(
+                "the request would cost up to {:.2} coins, {}",
+                coins, message
+            )))
+        };
+        if let Some(max) = self.max_per_request.filter(|max| coins > *max) {
+            return exceeded(format!("above the limit of {:.2} coins per request", max));
+        }
+        if let Some(balance) = balance.filter(|balance| balance - coins < self.min_balance) {
+            return Err(StraicoError::InsufficientCoins(format!(
+                "the request would cost up to {:.2} coins, {:.2} coins are left and {:.2} must remain",
+                coins, balance, self.min_balance
+            )));
+        }
+        let day = today();
+        let mut spending = self.lock();
+        spending.roll(day);
+        if let Some(max) = self.max_per_day {
+            if spending.total + coins > max {
+                return exceeded(format!(
+                    "{:.2} of the {:.2} coins of the daily budget are spent",
+                    spending.total, max
+                ));
+            }
+        }
+        let spent = spending.keys.get(key).copied().unwrap_or_default();
+        if let Some(max) = self.key_limits.get(key).copied().or(self.max_per_key) {
+            if spent + coins > max {
+                return exceeded(format!(
+                    "{:.2} of the {:.2} coins of the daily budget of the key are spent",
+                    spent, max
+                ));
+            }
+        }
+        spending.total += coins;
+        spending.keys.insert(key.to_string(), spent + coins);
+        Ok(Reservation {
+            budget: self,
+            key: key.to_string(),
+            day,
+            coins,
+            cost: None,
+            downgraded_from: None,
+        })
+    }
+
+    /// Adds coins to the spending of a day, or removes them if negative
+    fn add(&self, key: &str, day: u64, coins: f32) {
+        let mut spending = self.lock();
+        if spending.day != day {
+            return;
+        }
+        spending.total = (spending.total + coins).max(0.0);
+        if let Some(spent) = spending.keys.get_mut(key) {
+            *spent = (*spent + coins).max(0.0);
+        }
+    }
+
+    /// Locks the spending, recovering it if a thread panicked while holding it
+    fn lock(&self) -> std::sync::MutexGuard<'_, Spending> {
+        self.spending.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Reservation<'_> {
+    /// Returns the coins reserved, the estimated cost of the request
+    pub fn coins(&self) -> f32 {
+        self.coins
+    }
+
+    /// Returns the model requested before the request was sent to a cheaper one, if it was
+    pub fn downgraded_from(&self) -> Option<&str> {
+        self.downgraded_from.as_deref()
+    }
+
+    /// Replaces the reserved estimate with the actual cost of the request
+    ///
+    /// # Arguments
+    /// * `coins` - The price of the response, e.g. `CompletionData::overall_price().total()`
+    pub fn settle(mut self, coins: f32) {
+        self.cost = Some(coins);
+    }
+
+    /// Ends the reservation of a request that failed
+    ///
+    /// The coins are released if the request cannot have been charged: it was not sent,
+    /// or the API refused it with 400, 401, 402, 403 or 429. Otherwise, e.g. when the
+    /// response timed out, failed with a server error or could not be decoded, the estimate
+    /// counts as spent, since the completion may have been generated and billed.
+    ///
+    /// # Arguments
+    /// * `error` - Why the request failed
+    pub fn fail(mut self, error: &StraicoError) {
+        let charged = match error {
+            StraicoError::Transport(error) => match error.status() {
+                Some(status) => !refused(status),
+                None => !error.is_connect() && !error.is_builder(),
+            },
+            StraicoError::Status { status, .. } => !refused(*status),
+            StraicoError::Decode { .. }
+            | StraicoError::UnexpectedResponse(_)
+            | StraicoError::InvalidToolCall(_)
+            | StraicoError::InvalidOutput(_) => true,
+            _ => false,
+        };
+        if !charged {
+            self.cost = Some(0.0);
+        }
+    }
+
+    /// Records the model the request was downgraded from
+    fn downgraded(mut self, model: String) -> Self {
+        self.downgraded_from = Some(model);
+        self
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let cost = self.cost.unwrap_or(self.coins);
+        self.budget.add(&self.key, self.day, cost - self.coins);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_requests() {
+        let cases = [
+            (StatusCode::BAD_REQUEST, 0.0),
+            (StatusCode::UNAUTHORIZED, 0.0),
+            (StatusCode::PAYMENT_REQUIRED, 0.0),
+            (StatusCode::FORBIDDEN, 0.0),
+            (StatusCode::TOO_MANY_REQUESTS, 0.0),
+            (StatusCode::INTERNAL_SERVER_ERROR, 2.0),
+            (StatusCode::BAD_GATEWAY, 2.0),
+            (StatusCode::GATEWAY_TIMEOUT, 2.0),
+        ];
+        for (status, spent) in cases {
+            let budget = Budget::new();
+            let reservation = budget.reserve("key", 2.0, None).unwrap();
+            reservation.fail(&StraicoError::Status {
+                status,
+                body: String::new(),
+            });
+            assert_eq!(budget.spent_today(), spent, "{}", status);
+            assert_eq!(budget.spent_today_by("key"), spent, "{}", status);
+        }
+    }
+}
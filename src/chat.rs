@@ -1,9 +1,16 @@
 use crate::endpoints::completion::completion_request::Prompt;
 use crate::endpoints::completion::completion_response::{Message, ToolCall};
+use crate::endpoints::completion::tool_call_parser::extract_json;
+use crate::validation::{validate, Violation};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::borrow::Cow;
 use std::ops::Deref;
+use std::sync::LazyLock;
+
+mod templates;
+
+pub use templates::{ModelPattern, PromptRegistry};
 
 /// Represents a chat conversation as a sequence of messages.
 ///
@@ -58,6 +65,214 @@ pub enum Tool {
     },
 }
 
+/// Controls whether and which tools the model calls, as OpenAI's `tool_choice`.
+///
+/// Deserializes from "none", "auto" and "required", or from a named function object such
+/// as `{"type": "function", "function": {"name": "get_weather"}}`.
+///
+/// # Variants
+///
+/// * `None` - The tools are not offered to the model
+/// * `Auto` - The model decides whether to call tools
+/// * `Required` - The model must call at least one tool
+/// * `Function` - The model must call the named function
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(from = "ToolChoiceRepr")]
+pub enum ToolChoice {
+    None,
+    #[default]
+    Auto,
+    Required,
+    Function(String),
+}
+
+/// The OpenAI representations of a tool choice
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "\"none\", \"auto\", \"required\" or a named function tool choice"
+)]
+enum ToolChoiceRepr {
+    Mode(ToolChoiceMode),
+    Function { function: FunctionName },
+}
+
+/// The tool choices given as plain strings
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ToolChoiceMode {
+    None,
+    Auto,
+    Required,
+}
+
+/// The function object of a named tool choice
+#[derive(Deserialize)]
+struct FunctionName {
+    name: String,
+}
+
+impl From<ToolChoiceRepr> for ToolChoice {
+    fn from(value: ToolChoiceRepr) -> Self {
+        match value {
+            ToolChoiceRepr::Mode(ToolChoiceMode::None) => ToolChoice::None,
+            ToolChoiceRepr::Mode(ToolChoiceMode::Auto) => ToolChoice::Auto,
+            ToolChoiceRepr::Mode(ToolChoiceMode::Required) => ToolChoice::Required,
+            ToolChoiceRepr::Function { function } => ToolChoice::Function(function.name),
+        }
+    }
+}
+
+impl ToolChoice {
+    /// Checks the tool calls of an answer against the choice.
+    ///
+    /// With a named function, calls to any other function are dropped.
+    ///
+    /// # Arguments
+    ///
+    /// * `tool_calls` - The tool calls parsed from the answer
+    ///
+    /// # Returns
+    ///
+    /// The violations found, empty if the calls satisfy the choice
+    pub fn enforce(&self, tool_calls: &mut Option<Vec<ToolCall>>) -> Vec<Violation> {
+        match self {
+            ToolChoice::None | ToolChoice::Auto => Vec::new(),
+            ToolChoice::Required => {
+                if tool_calls.as_ref().is_some_and(|calls| !calls.is_empty()) {
+                    Vec::new()
+                } else {
+                    vec![Violation::new(
+                        "tool_calls",
+                        "expected at least one tool call within <tool_call></tool_call> tags",
+                    )]
+                }
+            }
+            ToolChoice::Function(name) => {
+                if let Some(calls) = tool_calls.as_mut() {
+                    calls.retain(|call| call.function_data().name() == name);
+                }
+                if tool_calls.as_ref().is_some_and(|calls| !calls.is_empty()) {
+                    Vec::new()
+                } else {
+                    *tool_calls = None;
+                    vec![Violation::new(
+                        "tool_calls",
+                        format!(
+                            "expected a call to the function '{}' within <tool_call></tool_call> tags",
+                            name
+                        ),
+                    )]
+                }
+            }
+        }
+    }
+}
+
+/// The format the answer of the model must have, as OpenAI's `response_format`.
+///
+/// # Variants
+///
+/// * `Text` - Free text, the default
+/// * `JsonObject` - A JSON object
+/// * `JsonSchema` - A JSON value following a schema
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ResponseFormat {
+    #[default]
+    Text,
+    JsonObject,
+    JsonSchema {
+        json_schema: JsonSchemaFormat,
+    },
+}
+
+/// A named JSON Schema the answer of the model must follow.
+///
+/// # Fields
+///
+/// * `name` - The name of the format
+/// * `description` - Optional description of what the answer represents
+/// * `schema` - The JSON Schema, any JSON value is accepted if missing
+/// * `strict` - Accepted for compatibility, answers are always validated
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub schema: Option<Value>,
+    #[serde(default)]
+    pub strict: Option<bool>,
+}
+
+impl ResponseFormat {
+    /// Builds the system instructions asking for the format, empty for `Text`.
+    pub fn instructions(&self) -> String {
+        let rules = "and nothing else: no explanations before or after it and no code fences.";
+        match self {
+            ResponseFormat::Text => String::new(),
+            ResponseFormat::JsonObject => format!(
+                "\n# Response Format\n\nAnswer with a single valid JSON object {}\n",
+                rules
+            ),
+            ResponseFormat::JsonSchema { json_schema } => {
+                let mut instructions = format!(
+                    "\n# Response Format\n\nAnswer with a single valid JSON value {}\n",
+                    rules
+                );
+                if let Some(description) = &json_schema.description {
+                    instructions.push_str(&format!("\nThe value is {}\n", description));
+                }
+                if let Some(schema) = &json_schema.schema {
+                    instructions.push_str(&format!(
+                        "\nThe value must follow the JSON schema \"{}\" given within <schema></schema> XML tags:\n<schema>\n{}\n</schema>\n",
+                        json_schema.name,
+                        serde_json::to_string_pretty(schema).unwrap()
+                    ));
+                }
+                instructions
+            }
+        }
+    }
+
+    /// Extracts the answer in this format from the text written by the model.
+    ///
+    /// For the JSON formats, the JSON value is taken out of any code fence or surrounding
+    /// text, repaired if slightly malformed, and checked against the format.
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The content of the answer
+    ///
+    /// # Returns
+    ///
+    /// The answer, serialized as compact JSON for the JSON formats, or the violations found
+    pub fn extract(&self, text: &str) -> Result<String, Vec<Violation>> {
+        if *self == ResponseFormat::Text {
+            return Ok(text.to_string());
+        }
+        let Some(value) = extract_json(text) else {
+            return Err(vec![Violation::new("", "the answer is not valid JSON")]);
+        };
+        let violations = match self {
+            ResponseFormat::JsonObject if !value.is_object() => {
+                vec![Violation::new("", "expected a JSON object")]
+            }
+            ResponseFormat::JsonSchema { json_schema } => json_schema
+                .schema
+                .as_ref()
+                .map_or_else(Vec::new, |schema| validate(&value, schema)),
+            _ => Vec::new(),
+        };
+        if violations.is_empty() {
+            Ok(value.to_string())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
 /// Represents different types of responses that can be generated by an AI assistant in a chat conversation.
 ///
 /// This enum defines two possible response formats that an AI assistant can provide:
@@ -77,6 +292,27 @@ pub enum AssistantMessage {
     ToolCalls(Vec<ToolCall>),
 }
 
+/// Renders a chat conversation into the single prompt string sent to the completion endpoint.
+///
+/// The Straico completion endpoint accepts one message, so the conversation has to be
+/// flattened. Implementations decide how roles are marked, e.g. with the special tokens
+/// of a model family (`PromptFormat`) or as a plain transcript (`PlainTranscript`).
+/// Renderers are looked up by model in a `PromptRegistry`.
+pub trait PromptRenderer: Send + Sync {
+    /// Renders the messages into a prompt.
+    ///
+    /// # Arguments
+    ///
+    /// * `messages` - The conversation, in order
+    /// * `instructions` - Extra system instructions, such as tool definitions, to append
+    ///   to the system message (may be empty)
+    ///
+    /// # Returns
+    ///
+    /// The rendered prompt text
+    fn render(&self, messages: &[Message], instructions: &str) -> String;
+}
+
 /// Defines the format for structuring chat prompts for different language models.
 ///
 /// The `PromptFormat` struct specifies how different parts of a chat conversation should be
@@ -93,23 +329,32 @@ pub enum AssistantMessage {
 /// * `assistant_pre` - Text to insert before assistant responses
 /// * `assistant_post` - Text to insert after assistant responses
 /// * `end` - Text to append at the very end of the prompt
-struct PromptFormat<'a> {
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct PromptFormat<'a> {
     /// Text to insert at the very beginning of the prompt
-    begin: &'a str,
+    #[serde(default)]
+    pub begin: Cow<'a, str>,
     /// Text to insert before system messages
-    system_pre: &'a str,
+    #[serde(default)]
+    pub system_pre: Cow<'a, str>,
     /// Text to insert after system messages
-    system_post: &'a str,
+    #[serde(default)]
+    pub system_post: Cow<'a, str>,
     /// Text to insert before user messages
-    user_pre: &'a str,
+    #[serde(default)]
+    pub user_pre: Cow<'a, str>,
     /// Text to insert after user messages
-    user_post: &'a str,
+    #[serde(default)]
+    pub user_post: Cow<'a, str>,
     /// Text to insert before assistant responses
-    assistant_pre: &'a str,
+    #[serde(default)]
+    pub assistant_pre: Cow<'a, str>,
     /// Text to insert after assistant responses
-    assistant_post: &'a str,
+    #[serde(default)]
+    pub assistant_post: Cow<'a, str>,
     /// Text to append at the very end of the prompt
-    end: &'a str,
+    #[serde(default)]
+    pub end: Cow<'a, str>,
 }
 
 impl Default for PromptFormat<'_> {
@@ -125,220 +370,510 @@ impl Default for PromptFormat<'_> {
     /// A `PromptFormat` instance initialized with default formatting strings for
     /// basic chat interactions.
     fn default() -> Self {
-        PromptFormat {
-            begin: "",
-            system_pre: "",
-            system_post: "\n",
-            user_pre: "### Instruction:\n",
-            user_post: "\n",
-            assistant_pre: "### Response:\n",
-            assistant_post: "\n",
-            end: "### Response:\n",
-        }
+        DEFAULT_PROMPT_FORMAT
     }
 }
 
+/// Defines the instruction/response prompt format used when no other format applies.
+pub const DEFAULT_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed(""),
+    system_pre: Cow::Borrowed(""),
+    system_post: Cow::Borrowed("\n"),
+    user_pre: Cow::Borrowed("### Instruction:\n"),
+    user_post: Cow::Borrowed("\n"),
+    assistant_pre: Cow::Borrowed("### Response:\n"),
+    assistant_post: Cow::Borrowed("\n"),
+    end: Cow::Borrowed("### Response:\n"),
+};
+
 /// Defines the prompt format used by Anthropic's language models like Claude.
-const ANTHROPIC_PROMPT_FORMAT: PromptFormat<'_> = PromptFormat {
-    begin: "",
-    system_pre: "",
-    system_post: "\n",
-    user_pre: "\nHuman: ",
-    user_post: "\n",
-    assistant_pre: "\nAssistant: ",
-    assistant_post: "\n",
-    end: "\nAssistant:",
+pub const ANTHROPIC_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed(""),
+    system_pre: Cow::Borrowed(""),
+    system_post: Cow::Borrowed("\n"),
+    user_pre: Cow::Borrowed("\nHuman: "),
+    user_post: Cow::Borrowed("\n"),
+    assistant_pre: Cow::Borrowed("\nAssistant: "),
+    assistant_post: Cow::Borrowed("\n"),
+    end: Cow::Borrowed("\nAssistant:"),
 };
 
 /// Defines the prompt format used by Mistral AI's language models.
-const MISTRAL_PROMPT_FORMAT: PromptFormat<'_> = PromptFormat {
-    begin: "",
-    system_pre: "[INST] <<SYS>>",
-    system_post: "<</SYS>> [/INST]",
-    user_pre: "[INST]",
-    user_post: "[/INST]",
-    assistant_pre: "",
-    assistant_post: "",
-    end: "",
+pub const MISTRAL_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed(""),
+    system_pre: Cow::Borrowed("[INST] <<SYS>>"),
+    system_post: Cow::Borrowed("<</SYS>> [/INST]"),
+    user_pre: Cow::Borrowed("[INST]"),
+    user_post: Cow::Borrowed("[/INST]"),
+    assistant_pre: Cow::Borrowed(""),
+    assistant_post: Cow::Borrowed(""),
+    end: Cow::Borrowed(""),
 };
 
 /// Defines the prompt format used by LLaMA 3 language models.
-const LLAMA3_PROMPT_FORMAT: PromptFormat<'_> = PromptFormat {
-    begin: "<|begin_of_text|>",
-    system_pre: "<|start_header_id|>system<|end_header_id|>\n\n",
-    system_post: "<|eot_id|>",
-    user_pre: "<|start_header_id|>user<|end_header_id|>\n\n",
-    user_post: "<|eot_id|>",
-    assistant_pre: "<|start_header_id|>assistant<|end_header_id|>\n\n",
-    assistant_post: "<|eot_id|>",
-    end: "<|start_header_id|>assistant<|end_header_id|>\n\n",
+pub const LLAMA3_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed("<|begin_of_text|>"),
+    system_pre: Cow::Borrowed("<|start_header_id|>system<|end_header_id|>\n\n"),
+    system_post: Cow::Borrowed("<|eot_id|>"),
+    user_pre: Cow::Borrowed("<|start_header_id|>user<|end_header_id|>\n\n"),
+    user_post: Cow::Borrowed("<|eot_id|>"),
+    assistant_pre: Cow::Borrowed("<|start_header_id|>assistant<|end_header_id|>\n\n"),
+    assistant_post: Cow::Borrowed("<|eot_id|>"),
+    end: Cow::Borrowed("<|start_header_id|>assistant<|end_header_id|>\n\n"),
 };
 
 /// Defines the prompt format used by Command-R language models.
-const COMMAND_R_PROMPT_FORMAT: PromptFormat<'_> = PromptFormat {
-    begin: "",
-    system_pre: "<|START_OF_TURN_TOKEN|><|SYSTEM_TOKEN|>",
-    system_post: "<|END_OF_TURN_TOKEN|>",
-    user_pre: "<|START_OF_TURN_TOKEN|><|USER_TOKEN|>",
-    user_post: "<|END_OF_TURN_TOKEN|>",
-    assistant_pre: "<|START_OF_TURN_TOKEN|><|CHATBOT_TOKEN|>",
-    assistant_post: "<|END_OF_TURN_TOKEN|>",
-    end: "<|START_OF_TURN_TOKEN|><|CHATBOT_TOKEN|>",
+pub const COMMAND_R_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed(""),
+    system_pre: Cow::Borrowed("<|START_OF_TURN_TOKEN|><|SYSTEM_TOKEN|>"),
+    system_post: Cow::Borrowed("<|END_OF_TURN_TOKEN|>"),
+    user_pre: Cow::Borrowed("<|START_OF_TURN_TOKEN|><|USER_TOKEN|>"),
+    user_post: Cow::Borrowed("<|END_OF_TURN_TOKEN|>"),
+    assistant_pre: Cow::Borrowed("<|START_OF_TURN_TOKEN|><|CHATBOT_TOKEN|>"),
+    assistant_post: Cow::Borrowed("<|END_OF_TURN_TOKEN|>"),
+    end: Cow::Borrowed("<|START_OF_TURN_TOKEN|><|CHATBOT_TOKEN|>"),
 };
 
 /// Defines the prompt format used by Qwen language models.
-const QWEN_PROMPT_FORMAT: PromptFormat<'_> = PromptFormat {
-    begin: "",
-    system_pre: "<|im_start|>system\n",
-    system_post: "<|im_end|>",
-    user_pre: "<|im_start|>user\n",
-    user_post: "<|im_end|>",
-    assistant_pre: "<|im_start|>assistant\n",
-    assistant_post: "<|im_end|>",
-    end: "<|im_start|>assistant\n",
+pub const QWEN_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed(""),
+    system_pre: Cow::Borrowed("<|im_start|>system\n"),
+    system_post: Cow::Borrowed("<|im_end|>"),
+    user_pre: Cow::Borrowed("<|im_start|>user\n"),
+    user_post: Cow::Borrowed("<|im_end|>"),
+    assistant_pre: Cow::Borrowed("<|im_start|>assistant\n"),
+    assistant_post: Cow::Borrowed("<|im_end|>"),
+    end: Cow::Borrowed("<|im_start|>assistant\n"),
 };
 
-impl<'a> Chat {
-    /// Converts a chat conversation into a formatted prompt string for language models
-    ///
-    /// # Arguments
-    ///
-    /// * `self` - The Chat instance containing the conversation messages
-    /// * `tools` - Optional vector of Tool instances that can be called by the model
-    /// * `model` - String identifier for the language model to format the prompt for
-    ///
-    /// # Returns
-    ///
-    /// Returns a Prompt instance containing the formatted conversation text with appropriate
-    /// model-specific formatting and any tool definitions
-    pub fn to_prompt(self, tools: Option<Vec<Tool>>, model: &str) -> Prompt<'a> {
-        let format = if model.to_lowercase().contains("anthropic") {
-            ANTHROPIC_PROMPT_FORMAT
-        } else if model.to_lowercase().contains("mistral") {
-            MISTRAL_PROMPT_FORMAT
-        } else if model.to_lowercase().contains("llama3") {
-            LLAMA3_PROMPT_FORMAT
-        } else if model.to_lowercase().contains("command") {
-            COMMAND_R_PROMPT_FORMAT
-        } else if model.to_lowercase().contains("qwen") {
-            QWEN_PROMPT_FORMAT
-        } else if model.to_lowercase().contains("anthropic") {
-            ANTHROPIC_PROMPT_FORMAT
-        } else {
-            PromptFormat::default()
-        };
+/// Defines the ChatML prompt format, with each turn on its own lines.
+pub const CHATML_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed(""),
+    system_pre: Cow::Borrowed("<|im_start|>system\n"),
+    system_post: Cow::Borrowed("<|im_end|>\n"),
+    user_pre: Cow::Borrowed("<|im_start|>user\n"),
+    user_post: Cow::Borrowed("<|im_end|>\n"),
+    assistant_pre: Cow::Borrowed("<|im_start|>assistant\n"),
+    assistant_post: Cow::Borrowed("<|im_end|>\n"),
+    end: Cow::Borrowed("<|im_start|>assistant\n"),
+};
 
-        let pre_tools: &str = r###"
-# Tools
+/// Defines the prompt format used by Google's Gemma language models.
+///
+/// Gemma has no system role, so system messages are sent as a user turn.
+pub const GEMMA_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed("<bos>"),
+    system_pre: Cow::Borrowed("<start_of_turn>user\n"),
+    system_post: Cow::Borrowed("<end_of_turn>\n"),
+    user_pre: Cow::Borrowed("<start_of_turn>user\n"),
+    user_post: Cow::Borrowed("<end_of_turn>\n"),
+    assistant_pre: Cow::Borrowed("<start_of_turn>model\n"),
+    assistant_post: Cow::Borrowed("<end_of_turn>\n"),
+    end: Cow::Borrowed("<start_of_turn>model\n"),
+};
 
-You may call one or more functions to assist with the user query
+/// Defines the prompt format used by Microsoft's Phi-3 and later language models.
+pub const PHI_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed(""),
+    system_pre: Cow::Borrowed("<|system|>\n"),
+    system_post: Cow::Borrowed("<|end|>\n"),
+    user_pre: Cow::Borrowed("<|user|>\n"),
+    user_post: Cow::Borrowed("<|end|>\n"),
+    assistant_pre: Cow::Borrowed("<|assistant|>\n"),
+    assistant_post: Cow::Borrowed("<|end|>\n"),
+    end: Cow::Borrowed("<|assistant|>\n"),
+};
 
-You are provided with available function signatures within <tools></tools> XML tags:
-<tools>
-"###;
-        let post_tools: &str = r###"
-</tools>
-# Tool Calls
+/// Defines the prompt format used by DeepSeek V2.5 and later language models.
+pub const DEEPSEEK_PROMPT_FORMAT: PromptFormat<'static> = PromptFormat {
+    begin: Cow::Borrowed("<｜begin▁of▁sentence｜>"),
+    system_pre: Cow::Borrowed(""),
+    system_post: Cow::Borrowed(""),
+    user_pre: Cow::Borrowed("<｜User｜>"),
+    user_post: Cow::Borrowed(""),
+    assistant_pre: Cow::Borrowed("<｜Assistant｜>"),
+    assistant_post: Cow::Borrowed("<｜end▁of▁sentence｜>"),
+    end: Cow::Borrowed("<｜Assistant｜>"),
+};
 
-For each tool call, return a json object with function name and arguments within \<tool_call\>\</tool_call\> XML tags:"
-\<tool_call\>{"name": <function-name>, "arguments": <args-json-object>}\</tool_call\>
-"###;
+/// Appends the `<tool_call>` markup of each tool call to the output.
+///
+/// The id of each call is included so that tool responses can refer to it.
+fn push_tool_calls(output: &mut String, tool_calls: &[ToolCall]) {
+    for tool_call in tool_calls {
+        let function = tool_call.function_data();
+        let call = format!(
+            "{{\"id\": {}, \"name\": {}, \"arguments\": {}}}",
+            Value::from(tool_call.id()),
+            Value::from(function.name()),
+            function.arguments()
+        );
+        output.push_str(&format!("<tool_call>\n{}\n</tool_call>", call));
+    }
+}
 
-        let mut tools_message = String::new();
-        if let Some(tools) = &tools {
-            tools_message.push_str(pre_tools);
-            for tool in tools {
-                tools_message.push_str(&format!("{}", serde_json::to_string_pretty(tool).unwrap()));
-            }
-            tools_message.push_str(post_tools);
-        }
+/// Appends a `<tool_response>` block tagged with the id and function name of the call it
+/// answers.
+///
+/// When the tool message carries no name, it is looked up among the tool calls of the
+/// conversation by id.
+fn push_tool_response(
+    output: &mut String,
+    messages: &[Message],
+    content: &str,
+    tool_call_id: Option<&str>,
+    name: Option<&str>,
+) {
+    let name = name.or_else(|| {
+        let id = tool_call_id?;
+        messages
+            .iter()
+            .filter_map(|message| match message {
+                Message::Assistant {
+                    tool_calls: Some(tool_calls),
+                    ..
+                } => Some(tool_calls),
+                _ => None,
+            })
+            .flatten()
+            .find(|call| call.id() == id)
+            .map(|call| call.function_data().name())
+    });
+    output.push_str("<tool_response");
+    if let Some(id) = tool_call_id {
+        output.push_str(&format!(" id={}", Value::from(id)));
+    }
+    if let Some(name) = name {
+        output.push_str(&format!(" name={}", Value::from(name)));
+    }
+    output.push_str(">\n");
+    output.push_str(content);
+    output.push_str("\n</tool_response>");
+}
+
+impl PromptRenderer for PromptFormat<'_> {
+    /// Renders the messages with the markers of the format.
+    ///
+    /// A leading system message receives the instructions, other system messages, e.g.
+    /// developer messages sent in the middle of a conversation, are rendered as system
+    /// turns where they appear. A conversation that does not start with a system message
+    /// is given a default one.
+    fn render(&self, messages: &[Message], instructions: &str) -> String {
+        let format = self;
         let mut output = String::new();
-        output.push_str(format.begin);
-        for (i, message) in self.0.iter().enumerate() {
-            match (i, message) {
-                (0, Message::System { content }) => {
-                    output.push_str(format.system_pre);
+        output.push_str(&format.begin);
+        if !matches!(messages.first(), None | Some(Message::System { .. })) {
+            output.push_str(&format!(
+                "{}You are a helpful assistant.\n{}{}\n",
+                format.system_pre, instructions, format.system_post
+            ));
+        }
+        for (i, message) in messages.iter().enumerate() {
+            match message {
+                Message::System { content } if i == 0 => {
+                    output.push_str(&format.system_pre);
                     if content.is_empty() {
                         output.push_str("You are a helpful assistant.\n");
                     } else {
                         output.push_str(&format!("{}\n", content));
                     }
-                    output.push_str(&tools_message);
-                    output.push_str(format.system_post);
+                    output.push_str(instructions);
+                    output.push_str(&format.system_post);
                 }
-                (_, Message::User { content }) => {
-                    if i == 0 {
-                        output.push_str(&format!(
-                            "{}You are a helpful assistant.\n{}{}\n",
-                            format.system_pre, &tools_message, format.system_post
-                        ))
-                    }
+                Message::System { content } => {
+                    output.push_str(&format!(
+                        "{}{}\n{}",
+                        format.system_pre, content, format.system_post
+                    ));
+                }
+                Message::User { content } => {
                     output.push_str(&format!(
                         "{}{}\n{}\n",
                         format.user_pre, content, format.user_post
                     ));
                 }
-
-                (
-                    _,
-                    Message::Assistant {
-                        content,
-                        tool_calls,
-                    },
-                ) if i > 0 => {
-                    output.push_str(format.assistant_pre);
-                    match (content, tool_calls) {
-                        (Some(c), None) => output.push_str(c),
-                        (None, Some(t)) => {
-                            for tool_call in t {
-                                let ToolCall::Function { function, .. } = tool_call;
-                                output.push_str(&format!(
-                                    "<tool_call>\n{}\n</tool_call>",
-                                    serde_json::to_string(function).unwrap()
-                                ));
-                            }
-                        }
-                        // Maybe this is unreachable? Depends on the provider never answering like this.
-                        (Some(c), Some(t)) => {
-                            output.push_str(c);
-                            for tool_call in t {
-                                let ToolCall::Function { function, .. } = tool_call;
-                                output.push_str(&format!(
-                                    "<tool_call>\n{}\n</tool_call>",
-                                    serde_json::to_string(function).unwrap()
-                                ));
-                            }
-                        }
-                        (None, None) => {}
+                Message::Assistant {
+                    content,
+                    tool_calls,
+                } => {
+                    output.push_str(&format.assistant_pre);
+                    if let Some(c) = content {
+                        output.push_str(c);
+                    }
+                    if let Some(t) = tool_calls {
+                        push_tool_calls(&mut output, t);
                     }
-                    output.push_str(format.assistant_post);
+                    output.push_str(&format.assistant_post);
                 }
-                (_, Message::Tool { content, .. }) if i > 0 => {
+                Message::Tool {
+                    content,
+                    tool_call_id,
+                    name,
+                } => {
                     // Check if previous message was not a tool
-                    if !matches!(self.0.get(i - 1), Some(Message::Tool { .. })) {
-                        output.push_str(format.user_pre);
+                    if i == 0 || !matches!(messages.get(i - 1), Some(Message::Tool { .. })) {
+                        output.push_str(&format.user_pre);
                     }
 
-                    output.push_str("\n<tool_response>\n");
-                    output.push_str(&content);
-                    output.push_str("\n</tool_response>");
+                    output.push('\n');
+                    push_tool_response(
+                        &mut output,
+                        messages,
+                        content,
+                        tool_call_id.as_deref(),
+                        name.as_deref(),
+                    );
 
                     // Check if next message is not a tool
-                    if i == self.0.len() - 1
-                        || !matches!(self.0.get(i + 1), Some(Message::Tool { .. }))
+                    if i == messages.len() - 1
+                        || !matches!(messages.get(i + 1), Some(Message::Tool { .. }))
                     {
-                        output.push_str(format.user_post);
+                        output.push_str(&format.user_post);
                     }
                 }
-                (_, _) => {
-                    eprintln!(
-                        "Message {:?} not in the expected position (found at index {})",
-                        message, i
+            }
+        }
+        output.push_str(&format.end);
+        output
+    }
+}
+
+/// Renders a conversation as a plain transcript without any special tokens.
+///
+/// Each message is written as a paragraph labelled with its role ("System:", "User:",
+/// "Assistant:" or "Tool:") and the prompt ends with "Assistant:". This suits hosted chat
+/// models, which receive the prompt as a single user message and should not see the raw
+/// control tokens of another model family.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlainTranscript;
+
+impl PromptRenderer for PlainTranscript {
+    fn render(&self, messages: &[Message], instructions: &str) -> String {
+        let mut system = String::new();
+        let mut turns = Vec::new();
+        for message in messages {
+            match message {
+                Message::System { content } => {
+                    system.push_str(content);
+                    system.push('\n');
+                }
+                Message::User { content } => turns.push(format!("User: {}", content)),
+                Message::Assistant {
+                    content,
+                    tool_calls,
+                } => {
+                    let mut turn = String::from("Assistant: ");
+                    if let Some(content) = content {
+                        turn.push_str(content);
+                    }
+                    if let Some(tool_calls) = tool_calls {
+                        push_tool_calls(&mut turn, tool_calls);
+                    }
+                    turns.push(turn);
+                }
+                Message::Tool {
+                    content,
+                    tool_call_id,
+                    name,
+                } => {
+                    let mut turn = String::from("Tool:\n");
+                    push_tool_response(
+                        &mut turn,
+                        messages,
+                        content,
+                        tool_call_id.as_deref(),
+                        name.as_deref(),
                     );
+                    turns.push(turn);
                 }
             }
         }
-        output.push_str(format.end);
+        if system.is_empty() {
+            system.push_str("You are a helpful assistant.\n");
+        }
+        system.push_str(instructions);
+
+        let mut output = format!("System: {}\n\n", system.trim_end());
+        for turn in turns {
+            output.push_str(&turn);
+            output.push_str("\n\n");
+        }
+        output.push_str("Assistant:");
+        output
+    }
+}
+
+/// Builds the system instructions describing the available tools and the expected
+/// `<tool_call>` markup, or an empty string if there are no tools.
+///
+/// # Arguments
+///
+/// * `tools` - The tools offered to the model
+/// * `choice` - Whether and which tools the model must call, `None` omits the tools
+/// * `parallel_tool_calls` - Whether the model may call several tools in one answer
+///
+/// # Returns
+///
+/// The instructions, to be added to the system prompt
+pub fn tool_instructions(tools: &[Tool], choice: &ToolChoice, parallel_tool_calls: bool) -> String {
+    let pre_tools: &str = r###"
+# Tools
+
+You may call one or more functions to assist with the user query
+
+You are provided with available function signatures within <tools></tools> XML tags:
+<tools>
+"###;
+    let post_tools: &str = r###"
+</tools>
+# Tool Calls
+
+For each tool call, return a json object with function name and arguments within \<tool_call\>\</tool_call\> XML tags:"
+\<tool_call\>{"name": <function-name>, "arguments": <args-json-object>}\</tool_call\>
+
+Each tool result is returned within <tool_response></tool_response> XML tags carrying the id of the call it answers.
+"###;
+
+    let mut tools_message = String::new();
+    if tools.is_empty() || *choice == ToolChoice::None {
+        return tools_message;
+    }
+    tools_message.push_str(pre_tools);
+    for tool in tools {
+        tools_message.push_str(&serde_json::to_string_pretty(tool).unwrap());
+    }
+    tools_message.push_str(post_tools);
+    match choice {
+        ToolChoice::Required => tools_message.push_str(
+            "\nYou must answer by calling at least one of the functions above, do not answer with text only.\n",
+        ),
+        ToolChoice::Function(name) => tools_message.push_str(&format!(
+            "\nYou must answer by calling the function \"{}\", do not answer with text only.\n",
+            name
+        )),
+        ToolChoice::None | ToolChoice::Auto => {}
+    }
+    if !parallel_tool_calls {
+        tools_message.push_str("\nCall at most one function per answer.\n");
+    }
+    tools_message
+}
+
+impl<'a> Chat {
+    /// Converts a chat conversation into a formatted prompt string for language models
+    ///
+    /// The format is chosen among the built-in renderers by the built-in model rules,
+    /// see `PromptRegistry::renderer_for`.
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The Chat instance containing the conversation messages
+    /// * `tools` - Optional vector of Tool instances that can be called by the model
+    /// * `model` - String identifier for the language model to format the prompt for
+    ///
+    /// # Returns
+    ///
+    /// Returns a Prompt instance containing the formatted conversation text with appropriate
+    /// model-specific formatting and any tool definitions
+    pub fn to_prompt(self, tools: Option<Vec<Tool>>, model: &str) -> Prompt<'a> {
+        static DEFAULT_REGISTRY: LazyLock<PromptRegistry> = LazyLock::new(PromptRegistry::default);
+        self.to_prompt_with(tools, model, &DEFAULT_REGISTRY)
+    }
+
+    /// Converts a chat conversation into a prompt using the renderer a registry holds for a model
+    ///
+    /// # Arguments
+    ///
+    /// * `self` - The Chat instance containing the conversation messages
+    /// * `tools` - Optional vector of Tool instances that can be called by the model
+    /// * `model` - String identifier for the language model to format the prompt for
+    /// * `registry` - The registry to look the renderer up in
+    ///
+    /// # Returns
+    ///
+    /// Returns a Prompt instance containing the rendered conversation and any tool definitions
+    pub fn to_prompt_with(
+        self,
+        tools: Option<Vec<Tool>>,
+        model: &str,
+        registry: &PromptRegistry,
+    ) -> Prompt<'a> {
+        self.render(tools.as_deref(), registry.renderer_for(model))
+    }
+
+    /// Renders a chat conversation into a prompt with a specific renderer
+    ///
+    /// # Arguments
+    ///
+    /// * `tools` - Optional slice of Tool instances that can be called by the model
+    /// * `renderer` - The renderer to use
+    ///
+    /// # Returns
+    ///
+    /// Returns a Prompt instance containing the rendered conversation and any tool definitions
+    pub fn render(&self, tools: Option<&[Tool]>, renderer: &dyn PromptRenderer) -> Prompt<'a> {
+        let instructions = tool_instructions(tools.unwrap_or_default(), &ToolChoice::Auto, true);
+        self.render_with_instructions(renderer, &instructions)
+    }
+
+    /// Renders a chat conversation into a prompt with a specific renderer and system
+    /// instructions, e.g. built with `tool_instructions`
+    ///
+    /// # Arguments
+    ///
+    /// * `renderer` - The renderer to use
+    /// * `instructions` - Text added to the system prompt
+    ///
+    /// # Returns
+    ///
+    /// Returns a Prompt instance containing the rendered conversation
+    pub fn render_with_instructions(
+        &self,
+        renderer: &dyn PromptRenderer,
+        instructions: &str,
+    ) -> Prompt<'a> {
+        let output = renderer.render(&self.0, instructions);
         Prompt::from(Cow::Owned(output))
     }
+
+    /// Appends a message to the conversation, e.g. to ask the model to correct an answer
+    pub fn push(&mut self, message: Message) {
+        self.0.push(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Reads messages written in the OpenAI format
+    fn messages(messages: Value) -> Vec<Message> {
+        serde_json::from_value(messages).unwrap()
+    }
+
+    #[test]
+    fn developer_message_in_conversation() {
+        let messages = messages(json!([
+            {"role": "system", "content": "Be brief."},
+            {"role": "user", "content": "Hi"},
+            {"role": "assistant", "content": "Hello!"},
+            {"role": "developer", "content": "Answer in French from now on."},
+            {"role": "user", "content": "How are you?"}
+        ]));
+        assert_eq!(
+            DEFAULT_PROMPT_FORMAT.render(&messages, "TOOLS\n"),
+            "Be brief.\nTOOLS\n\n### Instruction:\nHi\n\n\n### Response:\nHello!\n\
+             Answer in French from now on.\n\n### Instruction:\nHow are you?\n\n\n### Response:\n"
+        );
+    }
+
+    #[test]
+    fn conversation_without_system_message() {
+        let messages = messages(json!([{"role": "user", "content": "Hi"}]));
+        assert_eq!(
+            DEFAULT_PROMPT_FORMAT.render(&messages, ""),
+            "You are a helpful assistant.\n\n\n### Instruction:\nHi\n\n\n### Response:\n"
+        );
+    }
 }
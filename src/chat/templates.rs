@@ -0,0 +1,322 @@
+use super::{
+    PlainTranscript, PromptFormat, PromptRenderer, ANTHROPIC_PROMPT_FORMAT, CHATML_PROMPT_FORMAT,
+    COMMAND_R_PROMPT_FORMAT, DEEPSEEK_PROMPT_FORMAT, DEFAULT_PROMPT_FORMAT, GEMMA_PROMPT_FORMAT,
+    LLAMA3_PROMPT_FORMAT, MISTRAL_PROMPT_FORMAT, PHI_PROMPT_FORMAT, QWEN_PROMPT_FORMAT,
+};
+use crate::error::StraicoError;
+use regex::Regex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The built-in model rules as `(glob, format)` pairs, tried after every added rule
+const BUILTIN_RULES: &[(&str, &str)] = &[
+    ("*anthropic*", "anthropic"),
+    ("*mistral*", "mistral"),
+    ("*llama3*", "llama3"),
+    ("*command*", "command-r"),
+    ("*qwen*", "qwen"),
+];
+
+/// A pattern matched against model identifiers.
+///
+/// Patterns are either globs, where `*` matches any run of characters and `?` a single
+/// character, or regular expressions. Globs match the whole identifier and ignore case;
+/// regular expressions match anywhere unless anchored.
+#[derive(Clone, Debug)]
+pub struct ModelPattern(Regex);
+
+impl ModelPattern {
+    /// Creates a pattern from a glob, e.g. "google/gemma-*".
+    ///
+    /// # Returns
+    ///
+    /// The pattern, or `StraicoError::Config` if the glob cannot be compiled
+    pub fn glob(glob: &str) -> Result<Self, StraicoError> {
+        let mut pattern = String::from("(?i)^");
+        for c in glob.chars() {
+            match c {
+                '*' => pattern.push_str(".*"),
+                '?' => pattern.push('.'),
+                c => pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+            }
+        }
+        pattern.push('$');
+        Self::regex(&pattern)
+    }
+
+    /// Creates a pattern from a regular expression, e.g. "^deepseek/".
+    ///
+    /// # Returns
+    ///
+    /// The pattern, or `StraicoError::Config` if the expression is invalid
+    pub fn regex(regex: &str) -> Result<Self, StraicoError> {
+        Regex::new(regex)
+            .map(ModelPattern)
+            .map_err(|e| StraicoError::Config(format!("invalid model pattern '{}': {}", regex, e)))
+    }
+
+    /// Creates a pattern matching exactly one model identifier.
+    pub fn exact(model: &str) -> Self {
+        ModelPattern(Regex::new(&format!("^{}$", regex::escape(model))).unwrap())
+    }
+
+    /// Returns whether the pattern matches a model identifier.
+    pub fn matches(&self, model: &str) -> bool {
+        self.0.is_match(model)
+    }
+}
+
+/// A set of named prompt renderers and the rules deciding which model uses which.
+///
+/// The registry starts with the built-in formats, registered as "default", "anthropic",
+/// "mistral", "llama3", "command-r", "qwen", "chatml", "gemma", "phi", "deepseek" and
+/// "plain", and with built-in rules picking the Anthropic, Mistral, Llama 3, Command R and
+/// Qwen formats by model identifier. Further renderers and rules can be added at runtime
+/// or loaded from a template file with `load`. Rules added later take precedence over
+/// earlier ones, and models matching no rule use "default".
+#[derive(Clone)]
+pub struct PromptRegistry {
+    /// Renderers by name
+    renderers: HashMap<String, Arc<dyn PromptRenderer>>,
+    /// Model patterns and the name of the renderer they select, tried in order
+    rules: Vec<(ModelPattern, String)>,
+}
+
+impl Default for PromptRegistry {
+    /// Returns a registry holding the built-in renderers and rules.
+    fn default() -> Self {
+        let mut registry = PromptRegistry {
+            renderers: HashMap::new(),
+            rules: BUILTIN_RULES
+                .iter()
+                .map(|(glob, name)| (ModelPattern::glob(glob).unwrap(), name.to_string()))
+                .collect(),
+        };
+        registry
+            .register("default", DEFAULT_PROMPT_FORMAT)
+            .register("anthropic", ANTHROPIC_PROMPT_FORMAT)
+            .register("mistral", MISTRAL_PROMPT_FORMAT)
+            .register("llama3", LLAMA3_PROMPT_FORMAT)
+            .register("command-r", COMMAND_R_PROMPT_FORMAT)
+            .register("qwen", QWEN_PROMPT_FORMAT)
+            .register("chatml", CHATML_PROMPT_FORMAT)
+            .register("gemma", GEMMA_PROMPT_FORMAT)
+            .register("phi", PHI_PROMPT_FORMAT)
+            .register("deepseek", DEEPSEEK_PROMPT_FORMAT)
+            .register("plain", PlainTranscript);
+        registry
+    }
+}
+
+/// The content of a template file.
+///
+/// ```toml
+/// [formats.gemma2]
+/// begin = "<bos>"
+/// user_pre = "<start_of_turn>user\n"
+/// user_post = "<end_of_turn>\n"
+/// assistant_pre = "<start_of_turn>model\n"
+/// assistant_post = "<end_of_turn>\n"
+/// end = "<start_of_turn>model\n"
+///
+/// [[models]]
+/// glob = "google/gemma-2*"
+/// format = "gemma2"
+///
+/// [[models]]
+/// regex = "^deepseek/"
+/// format = "deepseek"
+/// ```
+///
+/// # Fields
+///
+/// * `formats` - Named prompt formats, fields left out are empty
+/// * `models` - Rules mapping model patterns to a format, the first matching rule wins
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TemplateFile {
+    #[serde(default)]
+    formats: HashMap<String, PromptFormat<'static>>,
+    #[serde(default)]
+    models: Vec<TemplateRule>,
+}
+
+/// A rule of a template file, mapping a glob or a regular expression to a format
+#[derive(Deserialize, Debug)]
+struct TemplateRule {
+    #[serde(flatten)]
+    pattern: TemplatePattern,
+    format: TemplateFormat,
+}
+
+/// The pattern of a template rule
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "lowercase")]
+enum TemplatePattern {
+    Glob(String),
+    Regex(String),
+}
+
+/// The format of a template rule, either the name of a registered format or inline fields
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum TemplateFormat {
+    Name(String),
+    Inline(PromptFormat<'static>),
+}
+
+impl PromptRegistry {
+    /// Registers a renderer under a name, replacing any renderer with the same name.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name used to assign the renderer to models
+    /// * `renderer` - The renderer, e.g. a `PromptFormat<'static>`
+    ///
+    /// # Returns
+    ///
+    /// The registry, for chaining
+    pub fn register<R: PromptRenderer + 'static>(
+        &mut self,
+        name: impl Into<String>,
+        renderer: R,
+    ) -> &mut Self {
+        self.renderers.insert(name.into(), Arc::new(renderer));
+        self
+    }
+
+    /// Adds a rule using a registered renderer for the models matching a pattern.
+    ///
+    /// The rule takes precedence over every rule added before it.
+    ///
+    /// # Arguments
+    ///
+    /// * `pattern` - The models the rule applies to
+    /// * `renderer` - The name of a registered renderer
+    ///
+    /// # Returns
+    ///
+    /// The registry, or `StraicoError::Config` if no renderer has that name
+    pub fn add_rule(
+        &mut self,
+        pattern: ModelPattern,
+        renderer: &str,
+    ) -> Result<&mut Self, StraicoError> {
+        self.check_renderer(renderer)?;
+        self.rules.insert(0, (pattern, renderer.to_string()));
+        Ok(self)
+    }
+
+    /// Assigns a registered renderer to a single model.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model identifier, e.g. "google/gemma-2-27b-it"
+    /// * `renderer` - The name of a registered renderer
+    ///
+    /// # Returns
+    ///
+    /// The registry, or `StraicoError::Config` if no renderer has that name
+    pub fn assign(&mut self, model: &str, renderer: &str) -> Result<&mut Self, StraicoError> {
+        self.add_rule(ModelPattern::exact(model), renderer)
+    }
+
+    /// Loads formats and rules from a template file.
+    ///
+    /// Files ending in `.toml` are read as TOML, any other file as JSON. The formats of the
+    /// file are registered by name, then its rules are added ahead of every existing rule,
+    /// keeping their order so the first matching rule of the file wins.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the template file
+    ///
+    /// # Returns
+    ///
+    /// The registry, `StraicoError::Io` if the file cannot be read, or `StraicoError::Config`
+    /// if it is malformed or refers to an unknown format
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut Self, StraicoError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)?;
+        let file = if path.extension().is_some_and(|ext| ext == "toml") {
+            toml::from_str(&content).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_str(&content).map_err(|e| e.to_string())
+        }
+        .map_err(StraicoError::Config)?;
+        self.apply(file)
+    }
+
+    /// Registers the formats and adds the rules of a parsed template file.
+    fn apply(&mut self, file: TemplateFile) -> Result<&mut Self, StraicoError> {
+        for (name, format) in file.formats {
+            self.register(name, format);
+        }
+        let mut rules = Vec::with_capacity(file.models.len());
+        for rule in file.models {
+            let (pattern, source) = match &rule.pattern {
+                TemplatePattern::Glob(glob) => (ModelPattern::glob(glob)?, glob),
+                TemplatePattern::Regex(regex) => (ModelPattern::regex(regex)?, regex),
+            };
+            let name = match rule.format {
+                TemplateFormat::Name(name) => {
+                    self.check_renderer(&name)?;
+                    name
+                }
+                TemplateFormat::Inline(format) => {
+                    let name = format!("pattern:{}", source);
+                    self.register(name.clone(), format);
+                    name
+                }
+            };
+            rules.push((pattern, name));
+        }
+        self.rules.splice(0..0, rules);
+        Ok(self)
+    }
+
+    /// Fails with `StraicoError::Config` if no renderer is registered under `name`.
+    fn check_renderer(&self, name: &str) -> Result<(), StraicoError> {
+        if self.renderers.contains_key(name) {
+            Ok(())
+        } else {
+            Err(StraicoError::Config(format!(
+                "unknown prompt format '{}'",
+                name
+            )))
+        }
+    }
+
+    /// Returns the renderer registered under a name.
+    pub fn get(&self, name: &str) -> Option<&dyn PromptRenderer> {
+        self.renderers.get(name).map(AsRef::as_ref)
+    }
+
+    /// Returns the names of the registered renderers, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.renderers.keys().map(AsRef::as_ref)
+    }
+
+    /// Returns the renderer to use for a model.
+    ///
+    /// The renderer of the first rule matching the model is used, most recently added
+    /// rules first and built-in rules last, falling back to "default".
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The model identifier
+    ///
+    /// # Returns
+    ///
+    /// The renderer for the model
+    pub fn renderer_for(&self, model: &str) -> &dyn PromptRenderer {
+        self.rules
+            .iter()
+            .find(|(pattern, _)| pattern.matches(model))
+            .and_then(|(_, name)| self.get(name))
+            .or_else(|| self.get("default"))
+            .unwrap_or(&DEFAULT_PROMPT_FORMAT)
+    }
+}
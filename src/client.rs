@@ -1,13 +1,12 @@
-use anyhow::Result;
-use futures::TryFutureExt;
-use reqwest::{Client, RequestBuilder, Response};
+use reqwest::header::HeaderMap;
+use reqwest::{Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
-use std::{fmt::Display, future::Future, marker::PhantomData};
+use std::{fmt::Display, marker::PhantomData, sync::Arc, time::Duration};
 
 #[cfg(feature = "file")]
-use crate::endpoints::file::{FileData, FileRequest};
+use crate::endpoints::file::{FileData, FileRequest, FileSource};
 #[cfg(feature = "file")]
-use reqwest::multipart::Form;
+use reqwest::multipart::{Form, Part};
 #[cfg(feature = "file")]
 use std::path::Path;
 
@@ -22,13 +21,18 @@ use crate::endpoints::user::UserData;
 
 use crate::endpoints::{
     completion::completion_request::CompletionRequest,
-    completion::completion_response::CompletionData, ApiResponseData,
+    completion::completion_response::CompletionData,
+    completion::fallback::{Fallback, FallbackCompletion, FallbackReason},
+    ApiResponseData,
 };
+use crate::error::StraicoError;
+use crate::limit::Limiter;
+use crate::retry::{RetryEvent, RetryPolicy};
 
 #[cfg(any(feature = "model", feature = "user"))]
 use crate::GetEndpoint;
 
-use crate::PostEndpoint;
+use crate::{Endpoint, PostEndpoint, DEFAULT_BASE_URL};
 
 /// Represents the state where no API key has been set for the request
 pub struct NoApiKey;
@@ -44,31 +48,230 @@ pub struct PayloadSet;
 /// * `Api` - Represents the authentication state (NoApiKey or ApiKeySet)
 /// * `Payload` - Represents the request payload state
 /// * `Response` - The expected response type from the API
-pub struct StraicoRequestBuilder<Api, Payload, Response>(
-    RequestBuilder,
-    PhantomData<Response>,
-    PhantomData<Payload>,
-    PhantomData<Api>,
-);
-
-impl Into<StraicoClient> for Client {
+pub struct StraicoRequestBuilder<Api, Payload, Response> {
+    /// The underlying HTTP request
+    request: RequestBuilder,
+    /// How failed attempts are retried, `None` to send the request once
+    retry: Option<Arc<RetryPolicy>>,
+    /// Whether the request can be repeated without being charged twice
+    idempotent: bool,
+    /// The limiter of the client and the endpoint of the request, `None` if not limited
+    limit: Option<(Arc<Limiter>, Endpoint)>,
+    marker: PhantomData<(Api, Payload, Response)>,
+}
+
+impl From<Client> for StraicoClient {
     /// Converts a reqwest::Client into a StraicoClient
     ///
+    /// The resulting client targets the default Straico API root (`DEFAULT_BASE_URL`).
+    ///
     /// # Returns
     ///
     /// A new StraicoClient wrapping the provided reqwest::Client
-    fn into(self) -> StraicoClient {
-        StraicoClient(self)
+    fn from(value: Client) -> Self {
+        StraicoClient::builder().client(value).build()
     }
 }
 
 /// A client for interacting with the Straico API
 ///
 /// Wraps a reqwest::Client and provides convenient methods for making API requests.
-/// Can be created using `StraicoClient::new()` or by converting a reqwest::Client
-/// using `Into<StraicoClient>`.
-#[derive(Clone, Default)]
-pub struct StraicoClient(Client);
+/// Every endpoint path is resolved against a configurable base URL, which defaults to
+/// the public Straico API. Can be created using `StraicoClient::new()`,
+/// `StraicoClient::builder()` or by converting a reqwest::Client using `Into<StraicoClient>`.
+#[derive(Clone)]
+pub struct StraicoClient {
+    /// The underlying HTTP client
+    client: Client,
+    /// The API root that endpoint paths are appended to, without a trailing slash
+    base_url: Arc<str>,
+    /// How failed requests are retried, `None` to send every request once
+    retry: Option<Arc<RetryPolicy>>,
+    /// The default time limit of each attempt of a request, `None` to wait indefinitely
+    timeout: Option<Duration>,
+    /// Rate and concurrency limits shared by every clone of the client, if any
+    limiter: Option<Arc<Limiter>>,
+}
+
+/// Builder for configuring a `StraicoClient`
+///
+/// # Fields
+///
+/// * `client` - Optional reqwest::Client to use, a default one is created otherwise
+/// * `base_url` - Optional API root to resolve endpoints against, defaults to `DEFAULT_BASE_URL`
+/// * `retry` - Optional policy for retrying failed requests, requests are sent once otherwise
+/// * `timeout` - Optional time limit of each attempt of a request
+/// * `connect_timeout` - Optional time limit for establishing connections
+/// * `read_timeout` - Optional time limit between two reads of a response
+/// * `limiter` - Optional rate and concurrency limits of the requests
+#[derive(Default)]
+pub struct StraicoClientBuilder {
+    client: Option<Client>,
+    base_url: Option<String>,
+    retry: Option<RetryPolicy>,
+    timeout: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    limiter: Option<Limiter>,
+}
+
+impl StraicoClientBuilder {
+    /// Sets the reqwest::Client used to perform HTTP requests
+    ///
+    /// # Arguments
+    ///
+    /// * `client` - A preconfigured reqwest::Client
+    ///
+    /// # Returns
+    ///
+    /// The builder with the HTTP client set
+    pub fn client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    /// Sets the base URL that every endpoint path is resolved against
+    ///
+    /// This allows pointing the client at a staging host, an egress gateway or a local
+    /// mock server. The URL may contain a path prefix, e.g. `http://gateway/straico`.
+    ///
+    /// # Arguments
+    ///
+    /// * `base_url` - The API root, with or without a trailing slash
+    ///
+    /// # Returns
+    ///
+    /// The builder with the base URL set
+    pub fn base_url<S: Into<String>>(mut self, base_url: S) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// Sets the policy used to retry requests that failed for a transient reason
+    ///
+    /// Completion and image requests cost coins and are only retried when they were
+    /// certainly not processed, unless the policy allows it, see `RetryPolicy`.
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The retry policy applied to every request of the client
+    ///
+    /// # Returns
+    ///
+    /// The builder with the retry policy set
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Sets the default time limit of a request, from sending it to reading the whole response
+    ///
+    /// The limit applies to each attempt when requests are retried, and can be overridden per
+    /// request with `StraicoRequestBuilder::timeout`. Requests running out of time fail with a
+    /// `StraicoError::Transport` error whose `is_timeout()` is true.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time limit of each attempt
+    ///
+    /// # Returns
+    ///
+    /// The builder with the timeout set
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the time limit for establishing a connection to the API
+    ///
+    /// This configures the HTTP client created by the builder, a client set with `client`
+    /// keeps its own settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time limit of the connection phase
+    ///
+    /// # Returns
+    ///
+    /// The builder with the connect timeout set
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the longest time to wait for data while reading a response
+    ///
+    /// This configures the HTTP client created by the builder, a client set with `client`
+    /// keeps its own settings.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time limit between two reads
+    ///
+    /// # Returns
+    ///
+    /// The builder with the read timeout set
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets the rate and concurrency limits of the requests of the client
+    ///
+    /// The limiter is shared by every clone of the built client, so parallel tasks using
+    /// clones are limited together.
+    ///
+    /// # Arguments
+    ///
+    /// * `limiter` - The limits, see `Limiter`
+    ///
+    /// # Returns
+    ///
+    /// The builder with the limiter set
+    pub fn limiter(mut self, limiter: Limiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Builds the configured `StraicoClient`
+    ///
+    /// # Returns
+    ///
+    /// A new StraicoClient using the configured HTTP client, base URL, retry policy and
+    /// timeouts
+    ///
+    /// # Panics
+    ///
+    /// Like `reqwest::Client::new`, if no client is set and the TLS backend cannot be
+    /// initialized
+    pub fn build(self) -> StraicoClient {
+        let base_url = self.base_url.as_deref().unwrap_or(DEFAULT_BASE_URL);
+        let client = self.client.unwrap_or_else(|| {
+            let mut builder = Client::builder();
+            if let Some(timeout) = self.connect_timeout {
+                builder = builder.connect_timeout(timeout);
+            }
+            if let Some(timeout) = self.read_timeout {
+                builder = builder.read_timeout(timeout);
+            }
+            builder.build().expect("TLS backend cannot be initialized")
+        });
+        StraicoClient {
+            client,
+            base_url: base_url.trim_end_matches('/').into(),
+            retry: self.retry.map(Arc::new),
+            timeout: self.timeout,
+            limiter: self.limiter.map(Arc::new),
+        }
+    }
+}
+
+impl Default for StraicoClient {
+    /// Creates a StraicoClient with a default reqwest::Client targeting `DEFAULT_BASE_URL`
+    fn default() -> Self {
+        StraicoClient::builder().build()
+    }
+}
 
 impl<'a> StraicoClient {
     /// Creates a new instance of StraicoClient with default configuration
@@ -83,6 +286,63 @@ impl<'a> StraicoClient {
         StraicoClient::default()
     }
 
+    /// Creates a builder for configuring a StraicoClient
+    ///
+    /// # Returns
+    ///
+    /// A `StraicoClientBuilder` with no options set
+    pub fn builder() -> StraicoClientBuilder {
+        StraicoClientBuilder::default()
+    }
+
+    /// Returns the base URL that endpoint paths are resolved against
+    ///
+    /// # Returns
+    ///
+    /// The configured API root, without a trailing slash
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Resolves an endpoint path against the configured base URL
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The endpoint whose path should be resolved
+    ///
+    /// # Returns
+    ///
+    /// The absolute URL of the endpoint
+    fn url<E: AsRef<str>>(&self, endpoint: E) -> String {
+        format!("{}/{}", self.base_url, endpoint.as_ref())
+    }
+
+    /// Wraps an HTTP request into a request builder using the retry policy, the timeout and
+    /// the limiter of the client
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The HTTP request
+    /// * `endpoint` - The endpoint the request is sent to
+    /// * `idempotent` - Whether the request can be repeated without being charged twice
+    fn request<T, U>(
+        &self,
+        mut request: RequestBuilder,
+        endpoint: Endpoint,
+        idempotent: bool,
+    ) -> StraicoRequestBuilder<NoApiKey, T, U> {
+        if let Some(timeout) = self.timeout {
+            request = request.timeout(timeout);
+        }
+        StraicoRequestBuilder {
+            request,
+            retry: self.retry.clone(),
+            idempotent,
+            limit: self.limiter.clone().map(|limiter| (limiter, endpoint)),
+            marker: PhantomData,
+        }
+    }
+
     /// Creates a request builder for the completion endpoint
     ///
     /// # Returns
@@ -91,7 +351,8 @@ impl<'a> StraicoClient {
     pub fn completion(
         self,
     ) -> StraicoRequestBuilder<NoApiKey, CompletionRequest<'a>, CompletionData> {
-        self.0.post(PostEndpoint::Completion.as_ref()).into()
+        let request = self.client.post(self.url(PostEndpoint::Completion));
+        self.request(request, PostEndpoint::Completion.into(), false)
     }
 
     /// Creates a request builder for the image generation endpoint
@@ -101,7 +362,8 @@ impl<'a> StraicoClient {
     /// A `StraicoRequestBuilder` configured for making image generation requests
     #[cfg(feature = "image")]
     pub fn image(self) -> StraicoRequestBuilder<NoApiKey, ImageRequest, ImageData> {
-        self.0.post(PostEndpoint::Image.as_ref()).into()
+        let request = self.client.post(self.url(PostEndpoint::Image));
+        self.request(request, PostEndpoint::Image.into(), false)
     }
 
     /// Creates a request builder for the file upload endpoint
@@ -111,7 +373,8 @@ impl<'a> StraicoClient {
     /// A `StraicoRequestBuilder` configured for making file upload requests
     #[cfg(feature = "file")]
     pub fn file(self) -> StraicoRequestBuilder<NoApiKey, FileRequest, FileData> {
-        self.0.post(PostEndpoint::File.as_ref()).into()
+        let request = self.client.post(self.url(PostEndpoint::File));
+        self.request(request, PostEndpoint::File.into(), true)
     }
 
     /// Creates a request builder for fetching available models
@@ -121,7 +384,8 @@ impl<'a> StraicoClient {
     /// A `StraicoRequestBuilder` configured for retrieving model information
     #[cfg(feature = "model")]
     pub fn models(self) -> StraicoRequestBuilder<NoApiKey, PayloadSet, ModelData> {
-        self.0.get(GetEndpoint::Models.as_ref()).into()
+        let request = self.client.get(self.url(GetEndpoint::Models));
+        self.request(request, GetEndpoint::Models.into(), true)
     }
 
     /// Creates a request builder for fetching user information
@@ -131,7 +395,8 @@ impl<'a> StraicoClient {
     /// A `StraicoRequestBuilder` configured for retrieving user data
     #[cfg(feature = "user")]
     pub fn user(self) -> StraicoRequestBuilder<NoApiKey, PayloadSet, UserData> {
-        self.0.get(GetEndpoint::User.as_ref()).into()
+        let request = self.client.get(self.url(GetEndpoint::User));
+        self.request(request, GetEndpoint::User.into(), true)
     }
 }
 
@@ -146,12 +411,58 @@ impl<T, U> StraicoRequestBuilder<NoApiKey, T, U> {
     ///
     /// A new StraicoRequestBuilder with the ApiKeySet state, preserving the payload and response types
     pub fn bearer_auth<K: Display>(self, api_key: K) -> StraicoRequestBuilder<ApiKeySet, T, U> {
-        self.0.bearer_auth(api_key).into()
+        self.map(|request| request.bearer_auth(api_key))
+    }
+}
+
+impl<T, U, V> StraicoRequestBuilder<T, U, V> {
+    /// Overrides the retry policy of the client for this request
+    ///
+    /// # Arguments
+    ///
+    /// * `policy` - The retry policy, e.g. `RetryPolicy::none()` to send the request once
+    ///
+    /// # Returns
+    ///
+    /// The request builder with the retry policy set
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(Arc::new(policy));
+        self
+    }
+
+    /// Overrides the timeout of the client for this request
+    ///
+    /// The limit covers sending the request and reading the whole response, and applies to
+    /// each attempt when the request is retried.
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The time limit of each attempt
+    ///
+    /// # Returns
+    ///
+    /// The request builder with the timeout set
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.map(|request| request.timeout(timeout))
+    }
+
+    /// Transforms the underlying HTTP request, keeping the retry settings
+    fn map<A, P, R>(
+        self,
+        f: impl FnOnce(RequestBuilder) -> RequestBuilder,
+    ) -> StraicoRequestBuilder<A, P, R> {
+        StraicoRequestBuilder {
+            request: f(self.request),
+            retry: self.retry,
+            idempotent: self.idempotent,
+            limit: self.limit,
+            marker: PhantomData,
+        }
     }
 }
 
 #[cfg(feature = "file")]
-type FormResult<T> = Result<StraicoRequestBuilder<T, PayloadSet, FileData>>;
+type FormResult<T> = Result<StraicoRequestBuilder<T, PayloadSet, FileData>, StraicoError>;
 #[cfg(feature = "file")]
 impl<T> StraicoRequestBuilder<T, FileRequest, FileData> {
     /// Creates a multipart form request for file upload
@@ -163,10 +474,75 @@ impl<T> StraicoRequestBuilder<T, FileRequest, FileData> {
     /// # Returns
     ///
     /// A Result containing a new StraicoRequestBuilder configured with the multipart form,
-    /// or an error if file creation fails
+    /// or a `StraicoError::Io` if the file cannot be read
     pub async fn multipart<U: AsRef<Path>>(self, file: U) -> FormResult<T> {
-        let form = Form::new().file("file", file).await?;
-        Ok(self.0.multipart(form).into())
+        self.upload(FileRequest::new().file(file).build()).await
+    }
+
+    /// Creates a multipart form request uploading a file held in memory
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The file content
+    /// * `file_name` - The file name reported to the API
+    /// * `mime_type` - Optional MIME type of the file, e.g. "application/pdf"
+    ///
+    /// # Returns
+    ///
+    /// A Result containing a new StraicoRequestBuilder configured with the multipart form,
+    /// or a `StraicoError::Transport` if the MIME type is invalid
+    pub fn multipart_bytes<B: Into<Vec<u8>>>(
+        self,
+        bytes: B,
+        file_name: &str,
+        mime_type: Option<&str>,
+    ) -> FormResult<T> {
+        let part = Part::bytes(bytes.into());
+        self.part(
+            part,
+            Some(file_name.to_string()),
+            mime_type.map(str::to_string),
+        )
+    }
+
+    /// Creates a multipart form request uploading the content described by a `FileRequest`
+    ///
+    /// Files on disk are read when this method is awaited, while readers and streams are
+    /// consumed as the request is sent, so large uploads are not buffered in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The file request built with `FileRequest::new()`
+    ///
+    /// # Returns
+    ///
+    /// A Result containing a new StraicoRequestBuilder configured with the multipart form,
+    /// or a `StraicoError::Io` if a file cannot be read and `StraicoError::Transport` if the
+    /// MIME type is invalid
+    pub async fn upload(self, file: FileRequest) -> FormResult<T> {
+        let (source, file_name, mime_type) = file.into_parts();
+        let part = match source {
+            FileSource::Path(path) => Part::file(path).await?,
+            FileSource::Bytes(bytes) => Part::stream(bytes),
+            FileSource::Stream(body) => Part::stream(body),
+        };
+        self.part(part, file_name, mime_type)
+    }
+
+    /// Attaches a multipart part as the `file` field, overriding its name and MIME type if given
+    fn part(
+        self,
+        mut part: Part,
+        file_name: Option<String>,
+        mime_type: Option<String>,
+    ) -> FormResult<T> {
+        if let Some(file_name) = file_name {
+            part = part.file_name(file_name);
+        }
+        if let Some(mime_type) = mime_type {
+            part = part.mime_str(&mime_type)?;
+        }
+        Ok(self.map(|request| request.multipart(Form::new().part("file", part))))
     }
 }
 
@@ -181,7 +557,8 @@ impl<K, T: Serialize, V> StraicoRequestBuilder<K, T, V> {
     ///
     /// A new StraicoRequestBuilder with the PayloadSet state, preserving the API key and response types
     pub fn json<U: Into<T>>(self, payload: U) -> StraicoRequestBuilder<K, PayloadSet, V> {
-        self.0.json(&payload.into()).into()
+        let payload = payload.into();
+        self.map(|request| request.json(&payload))
     }
 }
 
@@ -192,16 +569,143 @@ impl<V: for<'a> Deserialize<'a>> StraicoRequestBuilder<ApiKeySet, PayloadSet, V>
     /// and payload (if applicable), then attempt to parse the response as JSON into
     /// the expected response type.
     ///
+    /// Failed attempts are retried according to the retry policy, if any. Requests whose
+    /// body is streamed, such as uploads from readers, can only be sent once.
+    ///
+    /// # Returns
+    ///
+    /// A Result containing either:
+    /// - The deserialized API response data of type `ApiResponseData`
+    /// - A `StraicoError` describing why the request failed
+    ///
+    /// # Errors
+    ///
+    /// * `StraicoError::Transport` - The request could not be sent or the body could not be read
+    /// * `StraicoError::Status` - The API answered with a non-success status code
+    /// * `StraicoError::Decode` - The body was not valid JSON, the raw body is attached
+    /// * `StraicoError::Api` - The API reported `success: false` with an error message
+    /// * `StraicoError::InsufficientCoins` - The API refused the request for lack of coins
+    pub async fn send(self) -> Result<ApiResponseData, StraicoError> {
+        let Some(policy) = self.retry.as_deref() else {
+            return attempt(self.request, self.limit.as_ref())
+                .await
+                .map_err(|(error, _)| error);
+        };
+        let mut attempts = 1;
+        loop {
+            let Some(request) = self.request.try_clone() else {
+                return attempt(self.request, self.limit.as_ref())
+                    .await
+                    .map_err(|(error, _)| error);
+            };
+            let (error, headers) = match attempt(request, self.limit.as_ref()).await {
+                Ok(data) => return Ok(data),
+                Err(failure) => failure,
+            };
+            let delay = policy.retry_delay(attempts, &error, headers.as_ref(), self.idempotent);
+            let Some(delay) = delay else {
+                return Err(error);
+            };
+            policy.notify(&RetryEvent {
+                attempt: attempts,
+                delay,
+                error: &error,
+            });
+            tokio::time::sleep(delay).await;
+            attempts += 1;
+        }
+    }
+}
+
+impl<'a> StraicoRequestBuilder<ApiKeySet, CompletionRequest<'a>, CompletionData> {
+    /// Sends a completion request to the models of a fallback chain, one after the other,
+    /// until one of them gives an acceptable answer
+    ///
+    /// The models of the request are replaced by each model of the chain in turn, and only
+    /// the models actually tried are billed. If every model gives an answer that is not
+    /// acceptable, e.g. a refusal, the answer of the first of them is returned.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - The completion request, its models are ignored
+    /// * `fallback` - The models to try and when to move to the next one
+    ///
     /// # Returns
     ///
-    /// A Future that resolves to a Result containing either:
-    /// - The deserialized API response data of type `ApiResponseData<V>`
-    /// - A reqwest error if the request fails or JSON parsing fails
-    pub fn send(self) -> impl Future<Output = reqwest::Result<ApiResponseData>> {
-        self.0.send().and_then(Response::json)
+    /// The answer along with the model that gave it and the models passed over, or the
+    /// error of the last model if none answered. Errors that would affect every model, such
+    /// as a rejected API key, are returned right away.
+    pub async fn send_with_fallback(
+        self,
+        mut request: CompletionRequest<'a>,
+        fallback: &Fallback,
+    ) -> Result<FallbackCompletion, StraicoError> {
+        let check = |model: &str, result: &Result<CompletionData, StraicoError>| match result {
+            Ok(data) => match data.model(model) {
+                Some(answer) => fallback.check(answer.completion()),
+                None => Some(FallbackReason::EmptyContent),
+            },
+            Err(error) => fallback.check_error(error),
+        };
+        let attempt = |model: &str| {
+            request.set_models(std::borrow::Cow::Owned(model.to_string()));
+            // A completion request has no body yet, so it can always be copied
+            let attempt = self
+                .request
+                .try_clone()
+                .map(
+                    |builder| StraicoRequestBuilder::<ApiKeySet, PayloadSet, CompletionData> {
+                        request: builder.json(&request),
+                        retry: self.retry.clone(),
+                        idempotent: self.idempotent,
+                        limit: self.limit.clone(),
+                        marker: PhantomData,
+                    },
+                );
+            async move {
+                match attempt {
+                    Some(attempt) => attempt.send().await?.get_completion_data(),
+                    None => Err(StraicoError::InvalidRequest(String::from(
+                        "the completion request cannot be copied",
+                    ))),
+                }
+            }
+        };
+        fallback.run(attempt, check).await
     }
 }
 
+/// Sends a request once and decodes the response
+///
+/// When the client has a limiter, the request first waits for its turn and holds its
+/// permit until the response is read.
+///
+/// # Arguments
+///
+/// * `request` - The HTTP request
+/// * `limit` - The limiter of the client and the endpoint of the request, if any
+///
+/// # Returns
+///
+/// The response data, or the error along with the response headers if the API answered
+async fn attempt(
+    request: RequestBuilder,
+    limit: Option<&(Arc<Limiter>, Endpoint)>,
+) -> Result<ApiResponseData, (StraicoError, Option<HeaderMap>)> {
+    let _permit = match limit {
+        Some((limiter, endpoint)) => Some(limiter.acquire(*endpoint).await),
+        None => None,
+    };
+    let response = request.send().await.map_err(|e| (e.into(), None))?;
+    let status = response.status();
+    let headers = (!status.is_success()).then(|| response.headers().clone());
+    let body = response
+        .text()
+        .await
+        .map_err(|e| (e.into(), headers.clone()))?;
+    ApiResponseData::from_response(status, body).map_err(|e| (e, headers))
+}
+
 impl<T, U, V> From<RequestBuilder> for StraicoRequestBuilder<T, U, V> {
     /// Converts a RequestBuilder into a StraicoRequestBuilder
     ///
@@ -214,8 +718,15 @@ impl<T, U, V> From<RequestBuilder> for StraicoRequestBuilder<T, U, V> {
     ///
     /// # Returns
     ///
-    /// A new StraicoRequestBuilder wrapping the provided RequestBuilder with appropriate type parameters
+    /// A new StraicoRequestBuilder wrapping the provided RequestBuilder with appropriate type
+    /// parameters, sent once without retries
     fn from(value: RequestBuilder) -> Self {
-        StraicoRequestBuilder(value, PhantomData, PhantomData, PhantomData)
+        StraicoRequestBuilder {
+            request: value,
+            retry: None,
+            idempotent: false,
+            limit: None,
+            marker: PhantomData,
+        }
     }
 }
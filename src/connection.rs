@@ -0,0 +1,142 @@
+use crate::proxy_error::ProxyError;
+use actix_web::dev::Extensions;
+use actix_web::rt::net::TcpStream;
+use actix_web::HttpRequest;
+use std::any::Any;
+use std::future::Future;
+
+/// A duplicate of the socket of a client connection, used to notice when the client leaves
+///
+/// actix-web keeps running a handler after its client disconnected, so without it an
+/// abandoned completion would still be waited for and paid. The socket is duplicated when
+/// the connection is accepted, see `on_connect`, and watched by `until_disconnect`.
+pub struct ClientConnection(std::net::TcpStream);
+
+/// Stores a duplicate of the socket of every new connection in its connection data
+///
+/// # Arguments
+/// * `connection` - The I/O stream of the connection, a `TcpStream` for plain HTTP
+/// * `data` - The connection data, available to handlers with `HttpRequest::conn_data`
+pub fn on_connect(connection: &dyn Any, data: &mut Extensions) {
+    if let Some(socket) = connection.downcast_ref::<TcpStream>().and_then(duplicate) {
+        data.insert(ClientConnection(socket));
+    }
+}
+
+/// Duplicates the socket of a connection
+#[cfg(unix)]
+fn duplicate(stream: &TcpStream) -> Option<std::net::TcpStream> {
+    use std::os::fd::AsFd;
+    Some(stream.as_fd().try_clone_to_owned().ok()?.into())
+}
+
+/// Duplicates the socket of a connection
+#[cfg(windows)]
+fn duplicate(stream: &TcpStream) -> Option<std::net::TcpStream> {
+    use std::os::windows::io::AsSocket;
+    Some(stream.as_socket().try_clone_to_owned().ok()?.into())
+}
+
+/// Connections are not watched on other platforms
+#[cfg(not(any(unix, windows)))]
+fn duplicate(_: &TcpStream) -> Option<std::net::TcpStream> {
+    None
+}
+
+impl ClientConnection {
+    /// Returns a stream to wait on for the client to close the connection
+    fn watch(&self) -> Option<tokio::net::TcpStream> {
+        let socket = self.0.try_clone().ok()?;
+        socket.set_nonblocking(true).ok()?;
+        tokio::net::TcpStream::from_std(socket).ok()
+    }
+}
+
+/// Waits until the connection to the client fails, e.g. because the client reset it
+///
+/// A client that shuts down its side after sending the request, as some HTTP/1.0 clients
+/// do, still waits for the response, and a socket closed cleanly cannot be told apart from
+/// it, so the end of the incoming data is not taken as a disconnect. If the client sent
+/// more data, e.g. a pipelined request, the connection is assumed open as well.
+async fn closed(socket: tokio::net::TcpStream) {
+    let mut buf = [0; 1];
+    if socket.peek(&mut buf).await.is_ok() {
+        std::future::pending().await
+    }
+}
+
+/// Runs a future until it completes or the client of a request disconnects
+///
+/// When the connection to the client fails the future is dropped, which cancels the
+/// upstream requests it was waiting on. A client that only half-closes the connection
+/// still gets the response, see `closed`.
+///
+/// # Arguments
+/// * `req` - The request whose client is watched
+/// * `debug` - Whether to log cancellations
+/// * `future` - The work done for the request
+///
+/// # Returns
+/// The output of the future, or a `ProxyError::client_closed` error if the client left first
+pub fn until_disconnect<F, T>(
+    req: &HttpRequest,
+    debug: bool,
+    future: F,
+) -> impl Future<Output = Result<T, ProxyError>>
+where
+    F: Future<Output = Result<T, ProxyError>>,
+{
+    let socket = req
+        .conn_data::<ClientConnection>()
+        .and_then(ClientConnection::watch);
+    async move {
+        let Some(socket) = socket else {
+            return future.await;
+        };
+        tokio::select! {
+            result = future => result,
+            _ = closed(socket) => {
+                if debug {
+                    eprintln!("\n\n===== Client disconnected, request cancelled =====");
+                }
+                Err(ProxyError::client_closed())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+    use tokio::time::timeout;
+
+    /// Connects a client and returns it with the server side of the connection
+    async fn connect() -> (tokio::net::TcpStream, tokio::net::TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = tokio::net::TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (server, _) = listener.accept().await.unwrap();
+        (client, server)
+    }
+
+    #[tokio::test]
+    async fn half_close_is_not_a_disconnect() {
+        let (mut client, server) = connect().await;
+        client.shutdown().await.unwrap();
+        let waited = timeout(Duration::from_millis(200), closed(server)).await;
+        assert!(waited.is_err());
+    }
+
+    #[tokio::test]
+    async fn reset_is_a_disconnect() {
+        let (client, server) = connect().await;
+        client.set_linger(Some(Duration::ZERO)).unwrap();
+        drop(client);
+        let waited = timeout(Duration::from_secs(5), closed(server)).await;
+        assert!(waited.is_ok());
+    }
+}
@@ -8,7 +8,8 @@ pub mod model;
 #[cfg(feature = "user")]
 pub mod user;
 
-use anyhow::Result;
+use crate::error::StraicoError;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 
 /// A container for API response data
@@ -63,28 +64,160 @@ pub enum ResponseType {
 }
 
 impl ApiResponseData {
+    /// Decodes a raw HTTP response from the API into `ApiResponseData`
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - The HTTP status code of the response
+    /// * `body` - The raw response body
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ApiResponseData)` - A successful response carrying data
+    /// * `Err(StraicoError)` - The classified failure, see `StraicoRequestBuilder::send`
+    pub(crate) fn from_response(status: StatusCode, body: String) -> Result<Self, StraicoError> {
+        let parsed = serde_json::from_str::<ApiResponseData>(&body);
+        if !status.is_success() {
+            return Err(match parsed {
+                Ok(ApiResponseData {
+                    error: Some(err), ..
+                }) if status == StatusCode::PAYMENT_REQUIRED
+                    || err.to_lowercase().contains("coin") =>
+                {
+                    StraicoError::InsufficientCoins(err)
+                }
+                _ if status == StatusCode::PAYMENT_REQUIRED => {
+                    StraicoError::InsufficientCoins(body)
+                }
+                _ => StraicoError::Status { status, body },
+            });
+        }
+        match parsed {
+            Ok(ApiResponseData {
+                success: false,
+                error,
+                ..
+            }) => {
+                Err(StraicoError::from_api_message(error.unwrap_or_else(|| {
+                    String::from("request was not successful")
+                })))
+            }
+            Ok(data) => Ok(data),
+            Err(source) => Err(StraicoError::Decode { source, body }),
+        }
+    }
+
+    /// Extracts the response payload, turning API-reported errors into `StraicoError`
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ResponseType)` - The response payload
+    /// * `Err(StraicoError)` - The API error, or `UnexpectedResponse` if no data was returned
+    pub fn into_data(self) -> Result<ResponseType, StraicoError> {
+        match self {
+            ApiResponseData {
+                error: Some(err), ..
+            } => Err(StraicoError::from_api_message(err)),
+            ApiResponseData {
+                data: Some(data), ..
+            } => Ok(data),
+            ApiResponseData { .. } => Err(StraicoError::UnexpectedResponse("response data")),
+        }
+    }
+
     /// Extracts the completion data from the API response
     ///
     /// # Returns
     ///
     /// * `Ok(Completion)` - The completion data if the API call was successful
-    /// * `Err` - The error message if the API call failed
+    /// * `Err(StraicoError)` - The reason the completion could not be extracted
     ///
     /// # Errors
     ///
     /// Returns an error if:
     /// * The API call failed and returned an error message
-    /// * The response data was not of type Completion
-    pub fn get_completion(self) -> Result<completion::completion_response::Completion> {
-        match self {
-            ApiResponseData {
-                data: Some(ResponseType::Completion(data)),
-                ..
-            } => Ok(data.get_completion()),
-            ApiResponseData {
-                error: Some(err), ..
-            } => Err(anyhow::Error::msg(err)),
-            _ => unreachable!(),
+    /// * The response data was not of type Completion or contained no completion
+    pub fn get_completion(
+        self,
+    ) -> Result<completion::completion_response::Completion, StraicoError> {
+        match self.into_data()? {
+            ResponseType::Completion(data) => data
+                .get_completion()
+                .ok_or(StraicoError::UnexpectedResponse("at least one completion")),
+            #[allow(unreachable_patterns)]
+            _ => Err(StraicoError::UnexpectedResponse("completion data")),
+        }
+    }
+
+    /// Extracts the full completion data, covering every requested model
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(CompletionData)` - The per-model results and overall totals
+    /// * `Err(StraicoError)` - The API error, or `UnexpectedResponse` for other data
+    pub fn get_completion_data(
+        self,
+    ) -> Result<completion::completion_response::CompletionData, StraicoError> {
+        match self.into_data()? {
+            ResponseType::Completion(data) => Ok(data),
+            #[allow(unreachable_patterns)]
+            _ => Err(StraicoError::UnexpectedResponse("completion data")),
+        }
+    }
+
+    /// Extracts the file upload data from the API response
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(FileData)` - The uploaded file data if the API call was successful
+    /// * `Err(StraicoError)` - The API error, or `UnexpectedResponse` for other data
+    #[cfg(feature = "file")]
+    pub fn get_file(self) -> Result<file::FileData, StraicoError> {
+        match self.into_data()? {
+            ResponseType::File(data) => Ok(data),
+            _ => Err(StraicoError::UnexpectedResponse("file data")),
+        }
+    }
+
+    /// Extracts the image generation data from the API response
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ImageData)` - The generated image data if the API call was successful
+    /// * `Err(StraicoError)` - The API error, or `UnexpectedResponse` for other data
+    #[cfg(feature = "image")]
+    pub fn get_image(self) -> Result<image::ImageData, StraicoError> {
+        match self.into_data()? {
+            ResponseType::Image(data) => Ok(data),
+            _ => Err(StraicoError::UnexpectedResponse("image data")),
+        }
+    }
+
+    /// Extracts the model listing from the API response
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ModelData)` - The available models if the API call was successful
+    /// * `Err(StraicoError)` - The API error, or `UnexpectedResponse` for other data
+    #[cfg(feature = "model")]
+    pub fn get_models(self) -> Result<model::ModelData, StraicoError> {
+        match self.into_data()? {
+            ResponseType::Model(data) => Ok(data),
+            _ => Err(StraicoError::UnexpectedResponse("model data")),
+        }
+    }
+
+    /// Extracts the user information from the API response
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(UserData)` - The user information if the API call was successful
+    /// * `Err(StraicoError)` - The API error, or `UnexpectedResponse` for other data
+    #[cfg(feature = "user")]
+    pub fn get_user(self) -> Result<user::UserData, StraicoError> {
+        match self.into_data()? {
+            ResponseType::User(data) => Ok(data),
+            _ => Err(StraicoError::UnexpectedResponse("user data")),
         }
     }
 }
@@ -1,2 +1,4 @@
 pub mod completion_request;
 pub mod completion_response;
+pub mod fallback;
+pub mod tool_call_parser;
@@ -1,3 +1,4 @@
+use crate::error::StraicoError;
 use serde::Serialize;
 use std::borrow::Cow;
 
@@ -99,7 +100,7 @@ impl<'a> AsRef<str> for Prompt<'a> {
 ///
 /// The four slots allow for requesting completions from multiple models in parallel, though
 /// not all slots need to be filled. Typically only the first slot is used with a single model.
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct RequestModels<'a>(
     #[serde(skip_serializing_if = "Option::is_none")] Option<Cow<'a, str>>,
     #[serde(skip_serializing_if = "Option::is_none")] Option<Cow<'a, str>>,
@@ -187,6 +188,46 @@ impl<'a> From<Cow<'a, str>> for RequestModels<'a> {
     }
 }
 
+impl<'a> TryFrom<Vec<Cow<'a, str>>> for RequestModels<'a> {
+    type Error = StraicoError;
+
+    /// Converts a runtime list of model identifiers into a `RequestModels` instance.
+    ///
+    /// Unlike the array implementation, the number of models is only known at runtime,
+    /// so the conversion fails instead of silently dropping models.
+    ///
+    /// # Arguments
+    /// * `value` - A vector of one to four model identifiers
+    ///
+    /// # Returns
+    /// A new `RequestModels` instance, or `StraicoError::InvalidRequest` if the vector
+    /// is empty or holds more than four models
+    fn try_from(value: Vec<Cow<'a, str>>) -> Result<Self, Self::Error> {
+        if value.is_empty() || value.len() > 4 {
+            return Err(StraicoError::InvalidRequest(format!(
+                "between 1 and 4 models are required, got {}",
+                value.len()
+            )));
+        }
+        let mut models = value.into_iter();
+        Ok(RequestModels(
+            models.next(),
+            models.next(),
+            models.next(),
+            models.next(),
+        ))
+    }
+}
+
+impl<'a> RequestModels<'a> {
+    /// Returns an iterator over the configured model identifiers, in request order.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        [&self.0, &self.1, &self.2, &self.3]
+            .into_iter()
+            .filter_map(|x| x.as_deref())
+    }
+}
+
 /// A placeholder struct indicating that models have not been set in the builder.
 ///
 /// This struct is used as a type parameter in `CompletionRequestBuilder` to track
@@ -255,6 +296,7 @@ impl<'a> CompletionRequest<'a> {
     ///
     /// # Returns
     /// A `CompletionRequestBuilder` with default values and no models or message set.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new() -> CompletionRequestBuilder<'a, ModelsNotSet, MessageNotSet> {
         CompletionRequestBuilder::default()
     }
@@ -401,6 +443,31 @@ impl<'a> CompletionRequestBuilder<'a, ModelsSet<'a>, MessageSet<'a>> {
 }
 
 impl<'a> CompletionRequest<'a> {
+    /// Replaces the models of the request, e.g. to send it to the next model of a fallback
+    /// chain.
+    ///
+    /// # Arguments
+    /// * `models` - The model or models to use for completion
+    pub(crate) fn set_models<M: Into<RequestModels<'a>>>(&mut self, models: M) {
+        self.models = models.into();
+    }
+
+    /// Returns the models the completion is requested from.
+    ///
+    /// # Returns
+    /// A reference to the models, iterate them with `RequestModels::iter`.
+    pub fn get_models(&self) -> &RequestModels<'a> {
+        &self.models
+    }
+
+    /// Returns the prompt text of this completion request.
+    ///
+    /// # Returns
+    /// The prompt as a string slice.
+    pub fn get_message(&self) -> &str {
+        self.message.as_ref()
+    }
+
     /// Returns the maximum number of tokens configured for this completion request.
     ///
     /// # Returns
@@ -1,7 +1,16 @@
-use anyhow::Result;
+use super::tool_call_parser::{
+    parse_tagged_tool_calls, parse_tool_calls, InvalidToolCallPolicy, ParsedOutput,
+};
+use crate::chat::Tool;
+use crate::error::StraicoError;
+use crate::validation::{validate_tool_calls, Violation};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::borrow::Cow;
 use std::collections::HashMap;
+use std::fmt::{self, Display};
 
 /// Represents a collection of completion data with associated pricing and word count statistics.
 ///
@@ -135,6 +144,7 @@ pub struct Usage {
 /// * `message` - The actual response content and metadata
 /// * `index` - Zero-based position of this choice in the list of responses
 /// * `finish_reason` - Why the model stopped generating (e.g. "stop", "length", "tool_calls")
+/// * `model` - The model that produced this choice, set when completions are merged
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Choice {
     /// The message content and metadata for this choice
@@ -143,6 +153,9 @@ pub struct Choice {
     pub index: u8,
     /// Reason why the model stopped generating (e.g. "stop", "length", "tool_calls")
     pub finish_reason: Box<str>,
+    /// The model that produced this choice, if attributed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<Box<str>>,
 }
 
 /// Represents different types of messages in a conversation.
@@ -150,21 +163,138 @@ pub struct Choice {
 /// This enum is used to differentiate between messages from different roles in a chat or
 /// conversation context. It supports serialization/deserialization with serde and uses
 /// the "role" field as a tag with lowercase values.
+///
+/// Deserialization is lenient so that any OpenAI chat request is accepted: the "developer"
+/// role is read as `System` and the legacy "function" role as `Tool`, content may be null
+/// or an array of parts for every role, and unknown fields such as `name` on user messages
+/// are ignored.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "role", rename_all = "lowercase")]
 pub enum Message {
-    /// A message from a user, containing text content
-    User { content: Box<str> },
+    /// A message from a user, containing text or a list of typed content parts
+    User {
+        #[serde(default, deserialize_with = "deserialize_content")]
+        content: MessageContent,
+    },
     /// A message from the AI assistant, which may contain text content and/or tool calls
     Assistant {
+        #[serde(default, deserialize_with = "deserialize_optional_text")]
         content: Option<Box<str>>,
-        #[serde(skip_serializing_if = "Option::is_none")]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
         tool_calls: Option<Vec<ToolCall>>,
     },
     /// A system message providing context or instructions
-    System { content: Box<str> },
-    /// A message from a tool containing output or results
-    Tool { content: Box<str> },
+    #[serde(alias = "developer")]
+    System {
+        #[serde(default, deserialize_with = "deserialize_text")]
+        content: Box<str>,
+    },
+    /// A message from a tool containing output or results, with the id of the call it
+    /// answers and the name of the function called
+    #[serde(alias = "function")]
+    Tool {
+        #[serde(default, deserialize_with = "deserialize_text")]
+        content: Box<str>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tool_call_id: Option<Box<str>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        name: Option<Box<str>>,
+    },
+}
+
+/// Deserializes message content given as a string, as an array of parts or as null
+fn deserialize_content<'de, D>(deserializer: D) -> Result<MessageContent, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<MessageContent>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Deserializes text content given as a string, as an array of parts or as null, keeping
+/// only the text of the parts
+fn deserialize_optional_text<'de, D>(deserializer: D) -> Result<Option<Box<str>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(
+        Option::<MessageContent>::deserialize(deserializer)?.map(|content| match content {
+            MessageContent::Text(text) => text,
+            content => content.text().into(),
+        }),
+    )
+}
+
+/// Deserializes text content like `deserialize_optional_text`, reading null as empty text
+fn deserialize_text<'de, D>(deserializer: D) -> Result<Box<str>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(deserialize_optional_text(deserializer)?.unwrap_or_default())
+}
+
+/// Represents the content of a user message.
+///
+/// OpenAI clients send either a plain string or an array of typed parts, e.g. text mixed
+/// with images or files. Both forms are accepted and serialized back unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum MessageContent {
+    /// Plain text content
+    Text(Box<str>),
+    /// A list of text, image and file parts
+    Parts(Vec<ContentPart>),
+}
+
+/// Represents a single part of a multi-part user message.
+///
+/// The part kind is given by the "type" field, as in the OpenAI chat completion format.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    /// A fragment of text
+    Text { text: Box<str> },
+    /// An image given by URL, either remote or an inline `data:` URL
+    ImageUrl { image_url: ImageUrl },
+    /// A file given by identifier or inline data
+    File { file: FileReference },
+    /// A part of a kind that cannot be forwarded to Straico, e.g. input audio or a refusal
+    #[serde(other)]
+    Unsupported,
+}
+
+/// Represents the image of an `image_url` content part.
+///
+/// # Fields
+/// * `url` - The image URL, either remote or an inline base64 `data:` URL
+/// * `detail` - Optional level of detail requested for the image
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ImageUrl {
+    /// The image URL, either remote or an inline base64 `data:` URL
+    pub url: Box<str>,
+    /// The level of detail requested for the image (e.g. "low", "high", "auto")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<Box<str>>,
+}
+
+/// Represents the file of a `file` content part.
+///
+/// Either `file_id` or `file_data` is expected to be set.
+///
+/// # Fields
+/// * `file_id` - Optional identifier of a previously uploaded file
+/// * `file_data` - Optional inline file content, as a base64 `data:` URL or plain base64
+/// * `filename` - Optional name of the file
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FileReference {
+    /// Identifier of a previously uploaded file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_id: Option<Box<str>>,
+    /// Inline file content, as a base64 `data:` URL or plain base64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_data: Option<Box<str>>,
+    /// Name of the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<Box<str>>,
 }
 
 /// Represents a call to a function-based tool in the conversation.
@@ -192,9 +322,73 @@ pub struct FunctionData {
     /// The name of the function to call
     name: String,
     /// The arguments to pass to the function as a JSON Value
+    #[serde(deserialize_with = "deserialize_arguments")]
     arguments: Value,
 }
 
+/// Deserializes function arguments, decoding them when given as a JSON encoded string as
+/// in the OpenAI format
+fn deserialize_arguments<'de, D>(deserializer: D) -> Result<Value, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(match Value::deserialize(deserializer)? {
+        Value::String(arguments) => {
+            serde_json::from_str(&arguments).unwrap_or(Value::String(arguments))
+        }
+        arguments => arguments,
+    })
+}
+
+impl ToolCall {
+    /// Creates a call to a function with a newly generated unique id.
+    ///
+    /// Ids follow the OpenAI format: "call_" followed by 24 random alphanumeric characters.
+    pub fn function(function: FunctionData) -> Self {
+        let suffix: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(24)
+            .map(char::from)
+            .collect();
+        ToolCall::Function {
+            id: format!("call_{}", suffix),
+            function,
+        }
+    }
+
+    /// Returns the unique id of the call.
+    pub fn id(&self) -> &str {
+        let ToolCall::Function { id, .. } = self;
+        id
+    }
+
+    /// Returns the function called and its arguments.
+    pub fn function_data(&self) -> &FunctionData {
+        let ToolCall::Function { function, .. } = self;
+        function
+    }
+}
+
+impl FunctionData {
+    /// Creates the data of a call to the named function.
+    pub fn new(name: impl Into<String>, arguments: Value) -> Self {
+        FunctionData {
+            name: name.into(),
+            arguments,
+        }
+    }
+
+    /// Returns the name of the function to call.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the arguments to pass to the function.
+    pub fn arguments(&self) -> &Value {
+        &self.arguments
+    }
+}
+
 // Custom serializer to convert Value to String
 impl Serialize for FunctionData {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -209,89 +403,388 @@ impl Serialize for FunctionData {
     }
 }
 
+impl MessageContent {
+    /// Returns the text of the content.
+    ///
+    /// The text parts of multi-part content are joined with newlines, other parts are skipped.
+    ///
+    /// # Returns
+    /// The text, borrowed when the content is plain text
+    pub fn text(&self) -> Cow<'_, str> {
+        match self {
+            MessageContent::Text(text) => Cow::Borrowed(text),
+            MessageContent::Parts(parts) => {
+                let texts: Vec<&str> = parts
+                    .iter()
+                    .filter_map(|part| match part {
+                        ContentPart::Text { text } => Some(text.as_ref()),
+                        _ => None,
+                    })
+                    .collect();
+                Cow::Owned(texts.join("\n"))
+            }
+        }
+    }
+
+    /// Returns the parts of the content, empty for plain text.
+    pub fn parts(&self) -> &[ContentPart] {
+        match self {
+            MessageContent::Text(_) => &[],
+            MessageContent::Parts(parts) => parts,
+        }
+    }
+}
+
+impl Default for MessageContent {
+    /// Returns empty text content
+    fn default() -> Self {
+        MessageContent::Text(Box::default())
+    }
+}
+
+impl Display for MessageContent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text())
+    }
+}
+
+impl From<&str> for MessageContent {
+    fn from(value: &str) -> Self {
+        MessageContent::Text(value.into())
+    }
+}
+
+impl From<String> for MessageContent {
+    fn from(value: String) -> Self {
+        MessageContent::Text(value.into())
+    }
+}
+
 impl CompletionData {
     /// Extracts and returns the first completion from the `completions` HashMap.
     ///
+    /// When several models were requested the entry returned is arbitrary and the others
+    /// are dropped; use `completions()` or `into_completions()` to access every model.
+    ///
     /// # Returns
-    /// The `Completion` object from the first entry in the completions map.
-    pub fn get_completion(self) -> Completion {
+    /// The `Completion` object from the first entry in the completions map,
+    /// or `None` if the map is empty.
+    pub fn get_completion(self) -> Option<Completion> {
         let values = self.completions.into_values();
-        values.map(|x| x.completion).next().unwrap()
+        values.map(|x| x.completion).next()
+    }
+
+    /// Returns an iterator over the results of every requested model.
+    ///
+    /// # Returns
+    /// An iterator of `(model identifier, Model)` pairs, in no particular order
+    pub fn completions(&self) -> impl Iterator<Item = (&str, &Model)> {
+        self.completions.iter().map(|(k, v)| (k.as_ref(), v))
+    }
+
+    /// Consumes the data and returns an iterator over the results of every requested model.
+    ///
+    /// # Returns
+    /// An iterator of owned `(model identifier, Model)` pairs, in no particular order
+    pub fn into_completions(self) -> impl Iterator<Item = (Box<str>, Model)> {
+        self.completions.into_iter()
+    }
+
+    /// Returns the result of a specific model.
+    ///
+    /// # Arguments
+    /// * `model` - The model identifier as used in the request (e.g. "openai/gpt-4o")
+    ///
+    /// # Returns
+    /// The `Model` result for the given identifier, or `None` if it was not part of the response
+    pub fn model(&self, model: &str) -> Option<&Model> {
+        self.completions.get(model)
+    }
+
+    /// Merges the completions of every model into a single `Completion`.
+    ///
+    /// The choices of each model are appended in the order given by `order`, followed by
+    /// any model missing from it, and re-indexed sequentially. Each choice is attributed to
+    /// the model that produced it and token usage is summed across models.
+    ///
+    /// # Arguments
+    /// * `order` - The model identifiers in the order their choices should appear
+    ///
+    /// # Returns
+    /// The merged `Completion`, or `None` if the response contained no completion
+    pub fn into_merged_completion(mut self, order: &[&str]) -> Option<Completion> {
+        let mut models: Vec<(Box<str>, Model)> = order
+            .iter()
+            .filter_map(|m| self.completions.remove_entry(*m))
+            .collect();
+        let mut rest: Vec<_> = self.completions.into_iter().collect();
+        rest.sort_by(|a, b| a.0.cmp(&b.0));
+        models.extend(rest);
+
+        let names: Vec<&str> = models.iter().map(|(name, _)| name.as_ref()).collect();
+        let mut merged = Completion {
+            choices: Vec::new(),
+            model: names.join(",").into(),
+            usage: Usage {
+                prompt_tokens: 0,
+                completion_tokens: 0,
+                total_tokens: 0,
+            },
+            ..models.first()?.1.completion.clone()
+        };
+        for (name, model) in models {
+            let completion = model.completion;
+            merged.usage = merged.usage + completion.usage;
+            for mut choice in completion.choices {
+                choice.index = merged.choices.len() as u8;
+                choice.model = Some(name.clone());
+                merged.choices.push(choice);
+            }
+        }
+        Some(merged)
+    }
+
+    /// Returns the price breakdown across all completions.
+    pub fn overall_price(&self) -> &Price {
+        &self.overall_price
+    }
+
+    /// Returns the word count statistics across all completions.
+    pub fn overall_words(&self) -> &Words {
+        &self.overall_words
+    }
+}
+
+impl Model {
+    /// Returns the completion response produced by this model.
+    pub fn completion(&self) -> &Completion {
+        &self.completion
+    }
+
+    /// Consumes the model result and returns its completion response.
+    pub fn into_completion(self) -> Completion {
+        self.completion
+    }
+
+    /// Returns the price breakdown for this model completion.
+    pub fn price(&self) -> &Price {
+        &self.price
+    }
+
+    /// Returns the word count statistics for this model completion.
+    pub fn words(&self) -> &Words {
+        &self.words
+    }
+}
+
+impl Price {
+    /// Returns the cost for input/prompt tokens.
+    pub fn input(&self) -> f32 {
+        self.input
+    }
+
+    /// Returns the cost for output/completion tokens.
+    pub fn output(&self) -> f32 {
+        self.output
+    }
+
+    /// Returns the total combined cost of input and output.
+    pub fn total(&self) -> f32 {
+        self.total
+    }
+}
+
+impl Words {
+    /// Returns the number of words in the input/prompt text.
+    pub fn input(&self) -> u32 {
+        self.input
+    }
+
+    /// Returns the number of words in the output/completion text.
+    pub fn output(&self) -> u32 {
+        self.output
+    }
+
+    /// Returns the total combined word count of input and output.
+    pub fn total(&self) -> u32 {
+        self.total
+    }
+}
+
+impl std::ops::Add for Usage {
+    type Output = Usage;
+
+    /// Sums the token counts of two usage statistics.
+    fn add(self, rhs: Usage) -> Usage {
+        Usage {
+            prompt_tokens: self.prompt_tokens + rhs.prompt_tokens,
+            completion_tokens: self.completion_tokens + rhs.completion_tokens,
+            total_tokens: self.total_tokens + rhs.total_tokens,
+        }
+    }
+}
+
+impl Usage {
+    /// Returns the number of tokens in the input/prompt text.
+    pub fn prompt_tokens(&self) -> u32 {
+        self.prompt_tokens
+    }
+
+    /// Returns the number of tokens in the generated completion/output.
+    pub fn completion_tokens(&self) -> u32 {
+        self.completion_tokens
+    }
+
+    /// Returns the total combined token count.
+    pub fn total_tokens(&self) -> u32 {
+        self.total_tokens
     }
 }
 
 impl Completion {
+    /// Parses and processes the completion data, updating finish reasons and tool calls.
+    ///
+    /// Only `<tool_call>` blocks are converted into tool calls, unparseable blocks being
+    /// kept in the message content. Use `parse_with` when the tools offered are known.
+    ///
+    /// # Returns
+    /// Returns the processed completion wrapped in a Result
+    pub fn parse(self) -> Result<Completion, StraicoError> {
+        self.parse_messages(|text| parse_tagged_tool_calls(text, InvalidToolCallPolicy::default()))
+    }
+
     /// Parses and processes the completion data, updating finish reasons and tool calls.
     ///
     /// This function performs two main operations on the completion data:
-    /// 1. Processes any tool calls in the messages using `into_tool_calls_response()`
+    /// 1. Processes any tool calls in the messages using `extract_tool_calls()`, if tools
+    ///    were offered: `<tool_call>` blocks, fenced JSON and bare JSON calls are recognized,
+    ///    see `parse_tool_calls`. Without tools the content is left as is, so that JSON
+    ///    answers are not mistaken for calls.
     /// 2. Updates finish reasons based on content and existing finish reason values:
-    ///    - Sets to "tool_calls" if content is None
-    ///    - Changes "end_turn" to "stop"
+    ///    - Sets to "tool_calls" if tool calls were found
+    ///    - Changes provider specific stop reasons ("end_turn", "stop_sequence") to "stop"
+    ///    - Changes provider specific length reasons ("max_tokens") to "length"
+    ///
+    /// # Arguments
+    /// * `policy` - What to do with tool call blocks that cannot be parsed
+    /// * `tools_offered` - Whether the model was offered tools it may call
     ///
     /// # Returns
-    /// Returns the processed completion wrapped in a Result
-    pub fn parse(mut self) -> Result<Completion> {
+    /// Returns the processed completion, or `StraicoError::InvalidToolCall` if a tool call
+    /// cannot be parsed and the policy is `Error`
+    pub fn parse_with(
+        self,
+        policy: InvalidToolCallPolicy,
+        tools_offered: bool,
+    ) -> Result<Completion, StraicoError> {
+        if tools_offered {
+            self.parse_messages(|text| parse_tool_calls(text, policy))
+        } else {
+            self.parse_messages(|text| {
+                Ok(ParsedOutput {
+                    content: Some(text.to_string()),
+                    tool_calls: Vec::new(),
+                })
+            })
+        }
+    }
+
+    /// Extracts the tool calls of every choice with a parser and updates its finish reason
+    fn parse_messages(
+        mut self,
+        parse: impl Fn(&str) -> Result<ParsedOutput, StraicoError>,
+    ) -> Result<Completion, StraicoError> {
         for x in self.choices.iter_mut() {
-            x.message.into_tool_calls_response()?;
-            if let Message::Assistant { content, .. } = &x.message {
-                if content.is_none() {
+            x.message.extract_tool_calls(&parse)?;
+            if let Message::Assistant { tool_calls, .. } = &x.message {
+                if tool_calls.as_ref().is_some_and(|calls| !calls.is_empty()) {
                     x.finish_reason = "tool_calls".into();
-                } else if x.finish_reason == "end_turn".into() {
-                    x.finish_reason = "stop".into();
+                } else {
+                    match x.finish_reason.to_lowercase().as_str() {
+                        "end_turn" | "stop_sequence" => x.finish_reason = "stop".into(),
+                        "max_tokens" => x.finish_reason = "length".into(),
+                        _ => {}
+                    }
                 }
             }
         }
         Ok(self)
     }
+
+    /// Checks the tool calls of every choice against the tools offered to the model.
+    ///
+    /// See `validation::validate_tool_calls`, the paths of the violations are prefixed
+    /// with the choice, e.g. "choices/0/tool_calls/1/function/name".
+    ///
+    /// # Arguments
+    /// * `tools` - The tools offered to the model
+    ///
+    /// # Returns
+    /// Nothing, or `StraicoError::InvalidOutput` listing the calls to unknown functions
+    /// and the arguments not following the schema of their function
+    pub fn validate_tool_calls(&self, tools: &[Tool]) -> Result<(), StraicoError> {
+        let mut violations = Vec::new();
+        for (i, choice) in self.choices.iter().enumerate() {
+            if let Message::Assistant {
+                tool_calls: Some(calls),
+                ..
+            } = &choice.message
+            {
+                violations.extend(
+                    validate_tool_calls(calls, tools)
+                        .into_iter()
+                        .map(|violation| {
+                            Violation::new(
+                                format!("choices/{}/{}", i, violation.path),
+                                violation.message,
+                            )
+                        }),
+                );
+            }
+        }
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(StraicoError::InvalidOutput(violations))
+        }
+    }
 }
 
 impl Message {
-    /// Converts tool call markup in message content into structured tool calls.
+    /// Converts tool calls written in the content of an Assistant message into structured
+    /// tool calls.
+    ///
+    /// The content is parsed with a parser such as `parse_tool_calls`. When calls are found
+    /// they are stored in the message's `tool_calls` field and removed from the content,
+    /// leaving any surrounding text.
     ///
-    /// This function processes the content of an Assistant message to extract tool calls
-    /// that are marked up with XML-style tags (<tool_call>...</tool_call>). When found,
-    /// it:
-    /// - Extracts the JSON content from within the tool call tags
-    /// - Parses it into FunctionData structs
-    /// - Creates ToolCall::Function instances from the parsed data
-    /// - Stores the tool calls in the message's tool_calls field
-    /// - Removes the original content containing the markup
+    /// # Arguments
+    /// - `parse` - Splits the content into the remaining text and the calls
     ///
     /// # Returns
     /// - `Ok(())` if processing succeeds or if no tool calls are found
-    /// - `Err` if JSON parsing fails
-    fn into_tool_calls_response(&mut self) -> Result<()> {
+    /// - `Err` if a tool call cannot be parsed and the policy of the parser is `Error`
+    fn extract_tool_calls(
+        &mut self,
+        parse: impl Fn(&str) -> Result<ParsedOutput, StraicoError>,
+    ) -> Result<(), StraicoError> {
         if let Message::Assistant {
             content,
             tool_calls,
         } = self
         {
-            if let Some(optional_content) = content {
-                if optional_content.find("<tool_call>").is_some()
-                    || optional_content.find("</tool_call>").is_some()
-                {
-                    let re = regex::Regex::new(r"<tool_call>(.*?)</tool_call>").unwrap();
-                    let items = re
-                        .captures_iter(&optional_content.replace("\n", ""))
-                        .map(|cap| cap.get(1).unwrap().as_str().trim())
-                        .map(|s| {
-                            serde_json::from_str::<FunctionData>(s).map(|function_data| {
-                                ToolCall::Function {
-                                    id: String::from("func"),
-                                    function: function_data,
-                                }
-                            })
-                        })
-                        .collect::<Result<Vec<ToolCall>, _>>()?;
-
-                    let _ = tool_calls.insert(items);
-                    content.take();
+            if let Some(text) = content {
+                let parsed = parse(text)?;
+                if !parsed.tool_calls.is_empty() {
+                    let calls = parsed.tool_calls.into_iter().map(ToolCall::function);
+                    tool_calls.get_or_insert_with(Vec::new).extend(calls);
                 }
+                *content = parsed.content.map(Into::into);
             }
         }
         Ok(())
     }
+
     pub fn new_assistant_content(content: String) -> Self {
         Message::Assistant {
             content: Some(content.into()),
@@ -306,3 +799,87 @@ impl Message {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Builds a completion holding a single answer
+    fn completion(content: &str, finish_reason: &str) -> Completion {
+        serde_json::from_value(json!({
+            "choices": [{
+                "message": {"role": "assistant", "content": content},
+                "index": 0,
+                "finish_reason": finish_reason
+            }],
+            "object": "chat.completion",
+            "id": "cmpl-1",
+            "model": "openai/gpt-4o",
+            "created": 1,
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+        }))
+        .unwrap()
+    }
+
+    /// Returns the content, the names of the calls and the finish reason of the answer
+    fn answer(completion: &Completion) -> (Option<&str>, Vec<&str>, &str) {
+        let choice = &completion.choices[0];
+        let Message::Assistant {
+            content,
+            tool_calls,
+        } = &choice.message
+        else {
+            panic!("not an assistant message");
+        };
+        let names = tool_calls
+            .iter()
+            .flatten()
+            .map(|call| call.function_data().name())
+            .collect();
+        (content.as_deref(), names, &choice.finish_reason)
+    }
+
+    #[test]
+    fn finish_reasons() {
+        let call = r#"<tool_call>{"name": "weather", "arguments": {}}</tool_call>"#;
+        let invalid = "<tool_call>oops</tool_call>";
+        let cases = [
+            (
+                call,
+                InvalidToolCallPolicy::KeepAsText,
+                (None, vec!["weather"], "tool_calls"),
+            ),
+            (
+                invalid,
+                InvalidToolCallPolicy::KeepAsText,
+                (Some(invalid), vec![], "stop"),
+            ),
+            (invalid, InvalidToolCallPolicy::Drop, (None, vec![], "stop")),
+        ];
+        for (text, policy, expected) in cases {
+            let parsed = completion(text, "end_turn")
+                .parse_with(policy, true)
+                .unwrap();
+            assert_eq!(answer(&parsed), expected, "{:?} with {:?}", text, policy);
+        }
+        let parsed = completion("Cut", "max_tokens").parse().unwrap();
+        assert_eq!(answer(&parsed), (Some("Cut"), vec![], "length"));
+    }
+
+    #[test]
+    fn untagged_calls_need_tools() {
+        let text = r#"{"name": "weather", "arguments": {"city": "Paris"}}"#;
+        let parsed = completion(text, "stop").parse_with(InvalidToolCallPolicy::Error, true);
+        assert_eq!(answer(&parsed.unwrap()).1, ["weather"]);
+        for parsed in [
+            completion(text, "stop").parse_with(InvalidToolCallPolicy::Error, false),
+            completion(text, "stop").parse(),
+        ] {
+            assert_eq!(answer(&parsed.unwrap()), (Some(text), vec![], "stop"));
+        }
+        let text = r#"<tool_call>{"name": "weather"}</tool_call>"#;
+        let parsed = completion(text, "stop").parse_with(InvalidToolCallPolicy::Error, false);
+        assert_eq!(answer(&parsed.unwrap()), (Some(text), vec![], "stop"));
+    }
+}
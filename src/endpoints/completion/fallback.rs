@@ -0,0 +1,324 @@
+use super::completion_response::{Choice, Completion, CompletionData, Message};
+use crate::error::StraicoError;
+use regex::Regex;
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::sync::LazyLock;
+
+/// Matches the opening of an answer in which the model declines the request
+static REFUSAL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?i)^\W*(?:(?:i'm|i am) (?:very |really )?sorry|sorry|i apologi[sz]e|unfortunately)?[\s,.!]*(?:but\s+)?(?:as an ai[^.,]*,\s*)?i(?:'m| am)?\s+(?:can't|cannot|can not|won't|will not|unable to|not able to)\s+(?:help|assist|comply|provide|fulfill|do that|answer|complete|create|generate|write)",
+    )
+    .unwrap()
+});
+
+/// An ordered list of models tried one after the other until one answers
+///
+/// Unlike sending several models in one request, which runs and bills all of them, a
+/// fallback chain only moves to the next model when the previous one failed. A model fails
+/// when the request errors, when it refuses to answer, when its answer is empty, or when
+/// it stopped at the token limit. Each of these triggers can be turned off.
+///
+/// Errors that would affect every model are not retried with the next one: rejected API
+/// keys, missing coins and requests that are invalid before being sent.
+///
+/// ```no_run
+/// # async fn example() -> Result<(), straico::error::StraicoError> {
+/// use straico::client::StraicoClient;
+/// use straico::endpoints::completion::completion_request::CompletionRequest;
+/// use straico::endpoints::completion::fallback::Fallback;
+///
+/// let fallback = Fallback::new(["openai/gpt-4o", "anthropic/claude-3.5-sonnet"]);
+/// let request = CompletionRequest::new()
+///     .models("openai/gpt-4o")
+///     .message("Hello")
+///     .build();
+/// let response = StraicoClient::new()
+///     .completion()
+///     .bearer_auth("api-key")
+///     .send_with_fallback(request, &fallback)
+///     .await?;
+/// println!("answered by {}", response.model());
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Fallback {
+    models: Vec<String>,
+    on_error: bool,
+    on_refusal: bool,
+    on_empty: bool,
+    on_length: bool,
+}
+
+/// Why a model of a fallback chain was passed over
+///
+/// # Variants
+/// * `Error` - The request failed, holds the error message
+/// * `Refusal` - The model declined to answer
+/// * `EmptyContent` - The answer holds neither text nor tool calls
+/// * `TokenLimit` - The answer was cut at the token limit, or the prompt exceeded the
+///   context of the model
+#[derive(Clone, Debug, PartialEq)]
+pub enum FallbackReason {
+    Error(String),
+    Refusal,
+    EmptyContent,
+    TokenLimit,
+}
+
+/// The answer of a fallback chain, along with the model that gave it
+///
+/// # Fields
+/// * `data` - The response of the model that answered, by default the `CompletionData`
+///   including its price
+/// * `model` - The model that answered
+/// * `skipped` - The models passed over before it, with the reason
+#[derive(Debug)]
+pub struct FallbackCompletion<T = CompletionData> {
+    data: T,
+    model: Box<str>,
+    skipped: Vec<(Box<str>, FallbackReason)>,
+}
+
+impl Fallback {
+    /// Creates a fallback chain with every trigger enabled
+    ///
+    /// # Arguments
+    /// * `models` - The models to try, in order of preference
+    pub fn new<I, S>(models: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Fallback {
+            models: models.into_iter().map(Into::into).collect(),
+            on_error: true,
+            on_refusal: true,
+            on_empty: true,
+            on_length: true,
+        }
+    }
+
+    /// Sets whether a failed request moves to the next model
+    pub fn on_error(mut self, enabled: bool) -> Self {
+        self.on_error = enabled;
+        self
+    }
+
+    /// Sets whether an answer declining the request moves to the next model
+    pub fn on_refusal(mut self, enabled: bool) -> Self {
+        self.on_refusal = enabled;
+        self
+    }
+
+    /// Sets whether an empty answer moves to the next model
+    pub fn on_empty(mut self, enabled: bool) -> Self {
+        self.on_empty = enabled;
+        self
+    }
+
+    /// Sets whether an answer cut at the token limit moves to the next model
+    pub fn on_length(mut self, enabled: bool) -> Self {
+        self.on_length = enabled;
+        self
+    }
+
+    /// Returns the models of the chain, in order of preference
+    pub fn models(&self) -> &[String] {
+        &self.models
+    }
+
+    /// Checks whether an answer should be passed over for the next model
+    ///
+    /// # Arguments
+    /// * `completion` - The answer of a model, parsed or not
+    ///
+    /// # Returns
+    /// Why the answer is not acceptable, or `None` if it is
+    pub fn check(&self, completion: &Completion) -> Option<FallbackReason> {
+        if completion.choices.is_empty() {
+            return self.on_empty.then_some(FallbackReason::EmptyContent);
+        }
+        completion
+            .choices
+            .iter()
+            .find_map(|choice| self.check_choice(choice))
+    }
+
+    /// Checks whether a failed request should be retried with the next model
+    ///
+    /// # Arguments
+    /// * `error` - Why the request failed
+    ///
+    /// # Returns
+    /// The reason to move on, or `None` if the error would affect every model
+    pub fn check_error(&self, error: &StraicoError) -> Option<FallbackReason> {
+        let message = match error {
+            StraicoError::InvalidRequest(_)
+            | StraicoError::InsufficientCoins(_)
+            | StraicoError::Io(_)
+            | StraicoError::Config(_) => return None,
+            _ if error
+                .status()
+                .is_some_and(|status| status == 401 || status == 403) =>
+            {
+                return None
+            }
+            StraicoError::Api(message) => message.to_lowercase(),
+            _ => String::new(),
+        };
+        if message.contains("unauthorized") || message.contains("api key") {
+            None
+        } else if [
+            "context length",
+            "context window",
+            "too many tokens",
+            "token limit",
+        ]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+        {
+            self.on_length.then_some(FallbackReason::TokenLimit)
+        } else {
+            self.on_error
+                .then(|| FallbackReason::Error(error.to_string()))
+        }
+    }
+
+    /// Tries the models of the chain one after the other until one gives an acceptable
+    /// answer
+    ///
+    /// This is the loop behind `send_with_fallback`, for callers that send each model a
+    /// request of their own, e.g. a prompt rendered for that model. If every model gives an
+    /// answer that is not acceptable, the answer of the first of them is returned.
+    ///
+    /// # Arguments
+    /// * `attempt` - Sends the request to a model and returns its answer
+    /// * `check` - Decides on the result of a model: why it is passed over for the next
+    ///   model, or `None` to stop with it. `check` and `check_error` are the usual checks.
+    ///
+    /// # Returns
+    /// The first answer accepted along with the model that gave it and the models passed
+    /// over, or the first error not passed over, or the error of the last model if none
+    /// answered
+    pub async fn run<T, A, F, C>(
+        &self,
+        mut attempt: A,
+        mut check: C,
+    ) -> Result<FallbackCompletion<T>, StraicoError>
+    where
+        A: FnMut(&str) -> F,
+        F: Future<Output = Result<T, StraicoError>>,
+        C: FnMut(&str, &Result<T, StraicoError>) -> Option<FallbackReason>,
+    {
+        let mut skipped = Vec::new();
+        let mut rejected: Option<(T, Box<str>)> = None;
+        let mut last_error = None;
+        for model in &self.models {
+            let result = attempt(model).await;
+            let Some(reason) = check(model, &result) else {
+                return result
+                    .map(|data| FallbackCompletion::new(data, model.as_str().into(), skipped));
+            };
+            match result {
+                Ok(data) => {
+                    rejected.get_or_insert((data, model.as_str().into()));
+                }
+                Err(error) => last_error = Some(error),
+            }
+            skipped.push((Box::from(model.as_str()), reason));
+        }
+        match rejected {
+            Some((data, model)) => {
+                skipped.retain(|(skipped, _)| *skipped != model);
+                Ok(FallbackCompletion::new(data, model, skipped))
+            }
+            None => Err(last_error.unwrap_or_else(|| {
+                StraicoError::InvalidRequest(String::from("the fallback chain has no models"))
+            })),
+        }
+    }
+
+    /// Checks a single choice of an answer
+    fn check_choice(&self, choice: &Choice) -> Option<FallbackReason> {
+        match choice.finish_reason.to_lowercase().as_str() {
+            "length" | "max_tokens" if self.on_length => return Some(FallbackReason::TokenLimit),
+            "content_filter" | "refusal" | "safety" if self.on_refusal => {
+                return Some(FallbackReason::Refusal)
+            }
+            _ => {}
+        }
+        let Message::Assistant {
+            content,
+            tool_calls,
+        } = &choice.message
+        else {
+            return None;
+        };
+        if tool_calls.as_ref().is_some_and(|calls| !calls.is_empty()) {
+            return None;
+        }
+        let text = content.as_deref().unwrap_or_default().trim();
+        if self.on_empty && text.is_empty() {
+            Some(FallbackReason::EmptyContent)
+        } else if self.on_refusal && REFUSAL.is_match(&text.replace('\u{2019}', "'")) {
+            Some(FallbackReason::Refusal)
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for FallbackReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FallbackReason::Error(message) => write!(f, "{}", message),
+            FallbackReason::Refusal => write!(f, "the model refused to answer"),
+            FallbackReason::EmptyContent => write!(f, "the answer is empty"),
+            FallbackReason::TokenLimit => write!(f, "the token limit was reached"),
+        }
+    }
+}
+
+impl<T> FallbackCompletion<T> {
+    /// Creates the answer of a fallback chain
+    fn new(data: T, model: Box<str>, skipped: Vec<(Box<str>, FallbackReason)>) -> Self {
+        FallbackCompletion {
+            data,
+            model,
+            skipped,
+        }
+    }
+
+    /// Returns the model that answered
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// Returns the models passed over before the one that answered, with the reason
+    pub fn skipped(&self) -> &[(Box<str>, FallbackReason)] {
+        &self.skipped
+    }
+
+    /// Returns the response of the model that answered, including its price and word counts
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    /// Extracts the response of the model that answered
+    pub fn into_data(self) -> T {
+        self.data
+    }
+}
+
+impl FallbackCompletion {
+    /// Extracts the completion, each choice attributed to the model that answered
+    ///
+    /// # Returns
+    /// The completion, or `None` if the response contained none
+    pub fn into_completion(self) -> Option<Completion> {
+        self.data.into_merged_completion(&[&self.model])
+    }
+}
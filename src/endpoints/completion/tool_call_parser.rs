@@ -0,0 +1,641 @@
+use super::completion_response::FunctionData;
+use crate::error::StraicoError;
+use regex::Regex;
+use serde_json::{Map, Value};
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+/// Matches `<tool_call>` blocks, including the escaped `\<tool_call\>` form advertised in
+/// the prompt and a block left unclosed at the end of the output
+static TOOL_CALL_BLOCK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)\\?<tool_call\\?>(.*?)(?:\\?</tool_call\\?>|\z)").unwrap());
+
+/// Matches fenced code blocks, with or without a language tag, capturing their content
+static FENCED_BLOCK: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?s)```[\w-]*[ \t]*\n?(.*?)```").unwrap());
+
+/// Keys allowed in a tool call object found outside `<tool_call>` tags
+const CALL_KEYS: &[&str] = &["name", "arguments", "parameters", "type", "id"];
+
+/// Decides what happens to a `<tool_call>` block whose content cannot be parsed.
+///
+/// # Variants
+///
+/// * `KeepAsText` - The block is left in the message content as it was written
+/// * `Drop` - The block is removed from the message content
+/// * `Error` - Parsing fails with `StraicoError::InvalidToolCall`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InvalidToolCallPolicy {
+    #[default]
+    KeepAsText,
+    Drop,
+    Error,
+}
+
+impl FromStr for InvalidToolCallPolicy {
+    type Err = StraicoError;
+
+    /// Parses a policy from "keep", "drop" or "error".
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "keep" | "keep_as_text" | "text" => Ok(InvalidToolCallPolicy::KeepAsText),
+            "drop" => Ok(InvalidToolCallPolicy::Drop),
+            "error" => Ok(InvalidToolCallPolicy::Error),
+            _ => Err(StraicoError::Config(format!(
+                "unknown invalid tool call policy '{}', expected keep, drop or error",
+                s
+            ))),
+        }
+    }
+}
+
+/// The result of extracting tool calls from model output.
+///
+/// # Fields
+///
+/// * `content` - The text left once the tool calls are removed, `None` if only whitespace remains
+/// * `tool_calls` - The tool calls found, in order
+#[derive(Debug, Default, Clone)]
+pub struct ParsedOutput {
+    pub content: Option<String>,
+    pub tool_calls: Vec<FunctionData>,
+}
+
+impl ParsedOutput {
+    /// Builds the result from the remaining text and the calls found
+    fn new(content: String, tool_calls: Vec<FunctionData>) -> Self {
+        let content = content.trim();
+        ParsedOutput {
+            content: (!content.is_empty()).then(|| content.to_string()),
+            tool_calls,
+        }
+    }
+}
+
+/// Extracts the tool calls written by a model in its output.
+///
+/// Calls are looked for in this order, the first form found being used:
+/// 1. `<tool_call>` blocks, escaped or not, holding a call object or an array of calls
+/// 2. Fenced code blocks holding a call object or an array of calls
+/// 3. Call objects or arrays written as bare JSON at the start of the output
+///
+/// A call object has a `name` and its `arguments` (or `parameters`), optionally wrapped in
+/// an OpenAI style `{"type": "function", "function": {...}}` object. The JSON is repaired
+/// leniently, see `repair_json`. Text around the calls is kept as content. Fenced and bare
+/// JSON that does not look like a call is left untouched as text.
+///
+/// # Arguments
+///
+/// * `text` - The model output
+/// * `policy` - What to do with `<tool_call>` blocks that cannot be parsed
+///
+/// # Returns
+///
+/// The remaining content and the calls, or `StraicoError::InvalidToolCall` if a block
+/// cannot be parsed and the policy is `Error`
+pub fn parse_tool_calls(
+    text: &str,
+    policy: InvalidToolCallPolicy,
+) -> Result<ParsedOutput, StraicoError> {
+    if TOOL_CALL_BLOCK.is_match(text) {
+        return parse_tagged_tool_calls(text, policy);
+    }
+
+    let mut content = String::new();
+    let mut tool_calls = Vec::new();
+    let mut last = 0;
+    for captures in FENCED_BLOCK.captures_iter(text) {
+        let block = captures.get(0).unwrap();
+        if let Some(calls) = repair_json(&captures[1]).and_then(|v| function_calls(v, true)) {
+            content.push_str(&text[last..block.start()]);
+            tool_calls.extend(calls);
+            last = block.end();
+        }
+    }
+    if !tool_calls.is_empty() {
+        content.push_str(&text[last..]);
+        return Ok(ParsedOutput::new(content, tool_calls));
+    }
+
+    let mut rest = text.trim_start();
+    while rest.starts_with(['{', '[']) {
+        let end = json_end(rest).unwrap_or(rest.len());
+        match repair_json(&rest[..end]).and_then(|v| function_calls(v, true)) {
+            Some(calls) => tool_calls.extend(calls),
+            None => break,
+        }
+        rest = rest[end..].trim_start();
+    }
+    if tool_calls.is_empty() {
+        rest = text;
+    }
+    Ok(ParsedOutput::new(rest.to_string(), tool_calls))
+}
+
+/// Extracts the tool calls written by a model in `<tool_call>` blocks only.
+///
+/// Unlike `parse_tool_calls`, fenced and bare JSON is always left as text, which suits
+/// output that was not prompted with tools and may hold JSON of its own.
+///
+/// # Arguments
+///
+/// * `text` - The model output
+/// * `policy` - What to do with `<tool_call>` blocks that cannot be parsed
+///
+/// # Returns
+///
+/// The remaining content and the calls, or `StraicoError::InvalidToolCall` if a block
+/// cannot be parsed and the policy is `Error`
+pub fn parse_tagged_tool_calls(
+    text: &str,
+    policy: InvalidToolCallPolicy,
+) -> Result<ParsedOutput, StraicoError> {
+    let mut content = String::new();
+    let mut tool_calls = Vec::new();
+    let mut last = 0;
+    for captures in TOOL_CALL_BLOCK.captures_iter(text) {
+        let block = captures.get(0).unwrap();
+        content.push_str(&text[last..block.start()]);
+        last = block.end();
+        let inner = captures[1].trim();
+        let inner = FENCED_BLOCK
+            .captures(inner)
+            .map_or(inner, |fenced| fenced.get(1).unwrap().as_str());
+        match repair_json(inner).and_then(|v| function_calls(v, false)) {
+            Some(calls) => tool_calls.extend(calls),
+            None => match policy {
+                InvalidToolCallPolicy::KeepAsText => content.push_str(block.as_str()),
+                InvalidToolCallPolicy::Drop => {}
+                InvalidToolCallPolicy::Error => {
+                    return Err(StraicoError::InvalidToolCall(inner.to_string()))
+                }
+            },
+        }
+    }
+    content.push_str(&text[last..]);
+    Ok(ParsedOutput::new(content, tool_calls))
+}
+
+/// Converts a call object or an array of call objects into function data
+///
+/// With `strict`, objects must hold arguments and no keys other than those of a call, so
+/// that ordinary JSON found in the output is not mistaken for a call.
+fn function_calls(value: Value, strict: bool) -> Option<Vec<FunctionData>> {
+    match value {
+        Value::Array(items) if !items.is_empty() => items
+            .into_iter()
+            .map(|item| match item {
+                Value::Object(object) => function_call(object, strict),
+                _ => None,
+            })
+            .collect(),
+        Value::Object(object) => function_call(object, strict).map(|call| vec![call]),
+        _ => None,
+    }
+}
+
+/// Converts a single call object into function data
+fn function_call(mut object: Map<String, Value>, strict: bool) -> Option<FunctionData> {
+    if let Some(Value::Object(function)) = object.remove("function") {
+        return function_call(function, strict);
+    }
+    if strict && object.keys().any(|key| !CALL_KEYS.contains(&key.as_str())) {
+        return None;
+    }
+    let name = match object.remove("name")? {
+        Value::String(name) if !name.is_empty() => name,
+        _ => return None,
+    };
+    let arguments = match object
+        .remove("arguments")
+        .or_else(|| object.remove("parameters"))
+    {
+        Some(Value::String(arguments)) => repair_json(&arguments)
+            .filter(Value::is_object)
+            .unwrap_or(Value::String(arguments)),
+        Some(arguments) => arguments,
+        None if strict => return None,
+        None => Value::Object(Map::new()),
+    };
+    Some(FunctionData::new(name, arguments))
+}
+
+/// Finds a JSON value in free text.
+///
+/// The whole text is tried first, then the content of fenced code blocks, then the first
+/// balanced object or array in the text. Each candidate is repaired leniently.
+///
+/// # Arguments
+///
+/// * `text` - Text expected to contain a JSON value, e.g. a model answer
+///
+/// # Returns
+///
+/// The first value found, or `None`
+pub fn extract_json(text: &str) -> Option<Value> {
+    let trimmed = text.trim();
+    if trimmed.starts_with(['{', '[']) {
+        if let Some(value) = repair_json(trimmed) {
+            return Some(value);
+        }
+    }
+    for captures in FENCED_BLOCK.captures_iter(text) {
+        if let Some(value) = repair_json(&captures[1]) {
+            return Some(value);
+        }
+    }
+    let start = text.find(['{', '['])?;
+    let rest = &text[start..];
+    repair_json(&rest[..json_end(rest).unwrap_or(rest.len())])
+}
+
+/// Parses JSON written by a model, repairing common mistakes.
+///
+/// Valid JSON is parsed as is. Otherwise the following are fixed before parsing again:
+/// raw newlines and tabs inside strings, single-quoted strings, unquoted keys, trailing
+/// commas, Python's `True`, `False` and `None`, `NaN` and `Infinity`, which become `null`
+/// as JSON has no such numbers, and strings, objects and arrays left unclosed at the end
+/// of the text. Quotes escaped by doubling them, as in SQL's `'it''s'`, are not
+/// recognised, such text is not repaired.
+///
+/// # Arguments
+///
+/// * `text` - The JSON text
+///
+/// # Returns
+///
+/// The parsed value, or `None` if the text cannot be repaired
+pub fn repair_json(text: &str) -> Option<Value> {
+    let text = text.trim();
+    serde_json::from_str(text)
+        .ok()
+        .or_else(|| serde_json::from_str(&repair(text)).ok())
+}
+
+/// Rewrites almost-JSON text into JSON, see `repair_json`
+fn repair(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 8);
+    let mut closers = Vec::new();
+    let mut quote: Option<char> = None;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if let Some(q) = quote {
+            match c {
+                '\\' => match chars.next() {
+                    Some('\'') => out.push('\''),
+                    Some(escaped) => {
+                        out.push('\\');
+                        out.push(escaped);
+                    }
+                    None => {}
+                },
+                c if c == q => {
+                    out.push('"');
+                    quote = None;
+                }
+                '"' => out.push_str("\\\""),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                c => out.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => {
+                quote = Some(c);
+                out.push('"');
+            }
+            '{' => {
+                closers.push('}');
+                out.push(c);
+            }
+            '[' => {
+                closers.push(']');
+                out.push(c);
+            }
+            '}' | ']' => {
+                trim_trailing_comma(&mut out);
+                if closers.last() == Some(&c) {
+                    closers.pop();
+                }
+                out.push(c);
+            }
+            c if (c.is_alphabetic() || c == '_')
+                && !out.ends_with(|p: char| p.is_ascii_digit() || p == '.') =>
+            {
+                let mut word = String::from(c);
+                while let Some(&next) = chars.peek() {
+                    if !(next.is_alphanumeric() || next == '_') {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                match word.as_str() {
+                    "true" | "false" | "null" => out.push_str(&word),
+                    "True" => out.push_str("true"),
+                    "False" => out.push_str("false"),
+                    "None" => out.push_str("null"),
+                    "NaN" | "Infinity" => {
+                        if out.ends_with(['-', '+']) {
+                            out.pop();
+                        }
+                        out.push_str("null");
+                    }
+                    _ => {
+                        out.push('"');
+                        out.push_str(&word);
+                        out.push('"');
+                    }
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    if quote.is_some() {
+        out.push('"');
+    }
+    while let Some(closer) = closers.pop() {
+        trim_trailing_comma(&mut out);
+        out.push(closer);
+    }
+    out
+}
+
+/// Removes a comma left before a closing bracket
+fn trim_trailing_comma(out: &mut String) {
+    let trimmed = out.trim_end();
+    if trimmed.ends_with(',') {
+        out.truncate(trimmed.len() - 1);
+    }
+}
+
+/// Returns the byte offset just past the object or array that starts `text`
+///
+/// # Returns
+///
+/// The offset, or `None` if the value is not closed before the end of the text
+fn json_end(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '{' | '[' => depth += 1,
+            '}' | ']' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const POLICIES: [InvalidToolCallPolicy; 3] = [
+        InvalidToolCallPolicy::KeepAsText,
+        InvalidToolCallPolicy::Drop,
+        InvalidToolCallPolicy::Error,
+    ];
+
+    const WEATHER: &str = r#"{"name": "weather", "arguments": {"city": "Paris"}}"#;
+
+    /// The names and arguments of calls
+    type Calls = Vec<(String, Value)>;
+
+    /// Returns the content and the names and arguments of the calls found in a text
+    fn parse(
+        text: &str,
+        policy: InvalidToolCallPolicy,
+    ) -> Result<(Option<String>, Calls), StraicoError> {
+        let parsed = parse_tool_calls(text, policy)?;
+        let calls = parsed
+            .tool_calls
+            .iter()
+            .map(|call| (call.name().to_string(), call.arguments().clone()))
+            .collect();
+        Ok((parsed.content, calls))
+    }
+
+    /// Returns the expected names and arguments of calls
+    fn calls(calls: &[(&str, Value)]) -> Calls {
+        calls
+            .iter()
+            .map(|(name, arguments)| (name.to_string(), arguments.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn blocks_with_calls() {
+        let paris = || ("weather", json!({"city": "Paris"}));
+        let cases = [
+            // Tagged
+            (
+                format!("<tool_call>{}</tool_call>", WEATHER),
+                None,
+                vec![paris()],
+            ),
+            (
+                format!("Let me check.\n<tool_call>\n{}\n</tool_call>", WEATHER),
+                Some("Let me check."),
+                vec![paris()],
+            ),
+            (
+                format!(
+                    "<tool_call>[{}, {{\"name\": \"time\"}}]</tool_call>",
+                    WEATHER
+                ),
+                None,
+                vec![paris(), ("time", json!({}))],
+            ),
+            (
+                format!("<tool_call>\n```json\n{}\n```\n</tool_call>", WEATHER),
+                None,
+                vec![paris()],
+            ),
+            (format!("<tool_call>{}", WEATHER), None, vec![paris()]),
+            // Escaped tags
+            (
+                format!("\\<tool_call\\>{}\\</tool_call\\> Done.", WEATHER),
+                Some("Done."),
+                vec![paris()],
+            ),
+            // Fenced
+            (
+                format!("Sure.\n```json\n{}\n```\nDone.", WEATHER),
+                Some("Sure.\n\nDone."),
+                vec![paris()],
+            ),
+            (
+                r#"```
+{"type": "function", "function": {"name": "weather", "arguments": "{\"city\": \"Paris\"}"}}
+```"#
+                    .to_string(),
+                None,
+                vec![paris()],
+            ),
+            // Bare JSON
+            (
+                format!(
+                    "{}\n{{\"name\": \"time\", \"parameters\": {{}}}} Done.",
+                    WEATHER
+                ),
+                Some("Done."),
+                vec![paris(), ("time", json!({}))],
+            ),
+            (format!("  [{}]", WEATHER), None, vec![paris()]),
+        ];
+        for (text, content, expected) in cases {
+            for policy in POLICIES {
+                let (found_content, found) = parse(&text, policy).unwrap();
+                assert_eq!(found_content.as_deref(), content, "{:?}", text);
+                assert_eq!(found, calls(&expected), "{:?}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn repaired_calls() {
+        let cases = [
+            // Unclosed brackets
+            (
+                r#"<tool_call>{"name": "weather", "arguments": {"city": "Paris""#,
+                json!({"city": "Paris"}),
+            ),
+            // Raw newline in a string
+            (
+                "<tool_call>{\"name\": \"note\", \"arguments\": {\"text\": \"a\nb\"}}</tool_call>",
+                json!({"text": "a\nb"}),
+            ),
+            // Python literals, single quotes, unquoted keys and trailing commas
+            (
+                "```\n{name: 'note', arguments: {'text': 'it\\'s', done: True, due: None,},}\n```",
+                json!({"text": "it's", "done": true, "due": null}),
+            ),
+            // Non-finite numbers
+            (
+                r#"{"name": "note", "arguments": {"low": -Infinity, "high": Infinity, "x": NaN}}"#,
+                json!({"low": null, "high": null, "x": null}),
+            ),
+        ];
+        for (text, arguments) in cases {
+            let (_, found) = parse(text, InvalidToolCallPolicy::Error).unwrap();
+            assert_eq!(found.len(), 1, "{:?}", text);
+            assert_eq!(found[0].1, arguments, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn text_without_calls() {
+        let cases = [
+            "Plain text.",
+            "```json\n{\"city\": \"Paris\"}\n```",
+            "```rust\nfn main() {}\n```",
+            "Results:\n```json\n[1, 2, 3]\n```",
+            r#"{"name": "Paris", "population": 2100000}"#,
+            r#"{"name": "weather"}"#,
+            r#"The call would be {"name": "weather", "arguments": {}}"#,
+        ];
+        for text in cases {
+            for policy in POLICIES {
+                let (content, found) = parse(text, policy).unwrap();
+                assert_eq!(content.as_deref(), Some(text));
+                assert!(found.is_empty(), "{:?}", text);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_tagged_blocks() {
+        let text = r#"Before <tool_call>{"arguments": {}}</tool_call> after"#;
+        let cases = [
+            (InvalidToolCallPolicy::KeepAsText, Some(text)),
+            (InvalidToolCallPolicy::Drop, Some("Before  after")),
+            (InvalidToolCallPolicy::Error, None),
+        ];
+        for (policy, content) in cases {
+            match parse(text, policy) {
+                Ok((found, calls)) => {
+                    assert_eq!(found.as_deref(), content, "{:?}", policy);
+                    assert!(calls.is_empty());
+                }
+                Err(StraicoError::InvalidToolCall(block)) => {
+                    assert_eq!(content, None, "{:?}", policy);
+                    assert_eq!(block, r#"{"arguments": {}}"#);
+                }
+                Err(error) => panic!("{:?}: {}", policy, error),
+            }
+        }
+
+        let text = format!(
+            "<tool_call>{}</tool_call><tool_call>oops</tool_call>",
+            WEATHER
+        );
+        let (content, found) = parse(&text, InvalidToolCallPolicy::Drop).unwrap();
+        assert_eq!(content, None);
+        assert_eq!(found, calls(&[("weather", json!({"city": "Paris"}))]));
+    }
+
+    #[test]
+    fn repairs() {
+        let cases = [
+            (r#"{"a": [1, 2"#, Some(json!({"a": [1, 2]}))),
+            (r#"{"a": "unclosed"#, Some(json!({"a": "unclosed"}))),
+            (
+                "{\"a\": \"line\nbreak\ttab\"}",
+                Some(json!({"a": "line\nbreak\ttab"})),
+            ),
+            (r#"[1, 2,]"#, Some(json!([1, 2]))),
+            (
+                r#"{'a': "it's", 'b': 'say "hi"'}"#,
+                Some(json!({"a": "it's", "b": "say \"hi\""})),
+            ),
+            (r#"{a: 1e5, b: False}"#, Some(json!({"a": 1e5, "b": false}))),
+            (
+                r#"[Infinity, -Infinity, +Infinity, NaN]"#,
+                Some(json!([null, null, null, null])),
+            ),
+            // Doubled quotes are not an escape in JSON or Python, see `repair_json`
+            (r#"{a: 1, b: 'it''s'}"#, None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(repair_json(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn json_in_text() {
+        let cases = [
+            (r#"{"a": 1}"#, Some(json!({"a": 1}))),
+            ("Here:\n```json\n[1, 2]\n```", Some(json!([1, 2]))),
+            (
+                r#"The result is {"a": {"b": 2}} as asked."#,
+                Some(json!({"a": {"b": 2}})),
+            ),
+            ("No JSON here.", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(extract_json(text), expected, "{:?}", text);
+        }
+    }
+}
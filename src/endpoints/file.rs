@@ -1,34 +1,212 @@
+use bytes::Bytes;
+use futures::TryStream;
+use reqwest::Body;
 use serde::Deserialize;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
 
 #[derive(Debug, Deserialize)]
 pub struct FileData {
     pub url: String,
 }
 
-#[allow(dead_code)]
+/// A file upload request.
+///
+/// # Fields
+///
+/// * `source` - Where the content of the file is read from
+/// * `file_name` - Optional file name reported to the API
+/// * `mime_type` - Optional MIME type reported to the API
 pub struct FileRequest {
-    file: String,
+    source: FileSource,
+    file_name: Option<String>,
+    mime_type: Option<String>,
 }
+
+/// The origin of the content of an uploaded file.
+///
+/// # Variants
+///
+/// * `Path` - A file on the local filesystem, its name and MIME type are guessed from the path
+/// * `Bytes` - Content held in memory
+/// * `Stream` - Content produced by an async reader or a stream of chunks, sent without buffering
+pub enum FileSource {
+    Path(PathBuf),
+    Bytes(Bytes),
+    Stream(Body),
+}
+
+/// A builder pattern implementation for constructing `FileRequest` objects.
+///
+/// # Type Parameters
+///
+/// * `T` - Type state for the file content (either `FileNotSet` or `FileSource`)
 pub struct FileRequestBuilder<T> {
     file: T,
+    file_name: Option<String>,
+    mime_type: Option<String>,
 }
+
+/// Zero-sized type indicating that the file content has not been set in the builder
 pub struct FileNotSet;
 
 impl FileRequest {
+    /// Creates a new `FileRequestBuilder` with no file content set.
+    ///
+    /// The content must be set with `file`, `bytes`, `reader` or `stream` before calling `build()`.
+    ///
+    /// # Returns
+    ///
+    /// A new `FileRequestBuilder` instance with no fields set.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new() -> FileRequestBuilder<FileNotSet> {
-        FileRequestBuilder { file: FileNotSet }
+        FileRequestBuilder {
+            file: FileNotSet,
+            file_name: None,
+            mime_type: None,
+        }
+    }
+
+    /// Returns the origin of the file content.
+    pub fn source(&self) -> &FileSource {
+        &self.source
+    }
+
+    /// Returns the file name reported to the API, if set explicitly.
+    pub fn file_name(&self) -> Option<&str> {
+        self.file_name.as_deref()
+    }
+
+    /// Returns the MIME type reported to the API, if set explicitly.
+    pub fn mime_type(&self) -> Option<&str> {
+        self.mime_type.as_deref()
+    }
+
+    /// Splits the request into its content, file name and MIME type.
+    pub(crate) fn into_parts(self) -> (FileSource, Option<String>, Option<String>) {
+        (self.source, self.file_name, self.mime_type)
     }
 }
 
 impl FileRequestBuilder<FileNotSet> {
-    pub fn file<U: AsRef<Path>>(self, file: U) -> FileRequestBuilder<U> {
-        FileRequestBuilder { file }
+    /// Sets a file on the local filesystem as the content to upload.
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The path of the file, can be any type implementing AsRef<Path>
+    ///
+    /// # Returns
+    ///
+    /// A new `FileRequestBuilder` with the file content set
+    pub fn file<U: AsRef<Path>>(self, file: U) -> FileRequestBuilder<FileSource> {
+        self.source(FileSource::Path(file.as_ref().to_path_buf()))
+    }
+
+    /// Sets content held in memory as the content to upload.
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The file content, e.g. a `Vec<u8>` or `Bytes`
+    ///
+    /// # Returns
+    ///
+    /// A new `FileRequestBuilder` with the file content set
+    pub fn bytes<U: Into<Bytes>>(self, bytes: U) -> FileRequestBuilder<FileSource> {
+        self.source(FileSource::Bytes(bytes.into()))
+    }
+
+    /// Sets an async reader as the content to upload.
+    ///
+    /// The reader is consumed while the request is sent, without buffering it in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - Any `AsyncRead`, e.g. a `tokio::fs::File` or a socket
+    ///
+    /// # Returns
+    ///
+    /// A new `FileRequestBuilder` with the file content set
+    pub fn reader<R>(self, reader: R) -> FileRequestBuilder<FileSource>
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        self.stream(ReaderStream::new(reader))
+    }
+
+    /// Sets a stream of chunks as the content to upload.
+    ///
+    /// The stream is consumed while the request is sent, without buffering it in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `stream` - A fallible stream of chunks convertible into `Bytes`
+    ///
+    /// # Returns
+    ///
+    /// A new `FileRequestBuilder` with the file content set
+    pub fn stream<S>(self, stream: S) -> FileRequestBuilder<FileSource>
+    where
+        S: TryStream + Send + 'static,
+        S::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+        Bytes: From<S::Ok>,
+    {
+        self.source(FileSource::Stream(Body::wrap_stream(stream)))
+    }
+
+    /// Sets the file content while preserving the other fields.
+    fn source(self, source: FileSource) -> FileRequestBuilder<FileSource> {
+        FileRequestBuilder {
+            file: source,
+            file_name: self.file_name,
+            mime_type: self.mime_type,
+        }
+    }
+}
+
+impl<T> FileRequestBuilder<T> {
+    /// Sets the file name reported to the API.
+    ///
+    /// Required for in-memory and streamed content, for which no name can be inferred.
+    ///
+    /// # Arguments
+    ///
+    /// * `file_name` - The file name, e.g. "report.pdf"
+    ///
+    /// # Returns
+    ///
+    /// The builder with the file name set
+    pub fn file_name<U: Into<String>>(mut self, file_name: U) -> Self {
+        self.file_name = Some(file_name.into());
+        self
+    }
+
+    /// Sets the MIME type reported to the API.
+    ///
+    /// # Arguments
+    ///
+    /// * `mime_type` - The MIME type, e.g. "application/pdf"
+    ///
+    /// # Returns
+    ///
+    /// The builder with the MIME type set
+    pub fn mime_type<U: Into<String>>(mut self, mime_type: U) -> Self {
+        self.mime_type = Some(mime_type.into());
+        self
     }
 }
 
-impl FileRequestBuilder<String> {
+impl FileRequestBuilder<FileSource> {
+    /// Constructs a `FileRequest` from the builder once the file content has been set.
+    ///
+    /// # Returns
+    ///
+    /// A fully constructed `FileRequest`
     pub fn build(self) -> FileRequest {
-        FileRequest { file: self.file }
+        FileRequest {
+            source: self.file,
+            file_name: self.file_name,
+            mime_type: self.mime_type,
+        }
     }
 }
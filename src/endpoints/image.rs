@@ -1,4 +1,6 @@
+use crate::error::StraicoError;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 /// A structure representing data about an image generation request response.
 ///
@@ -44,6 +46,66 @@ pub struct ImageRequest {
     variations: u8,
 }
 
+/// The image formats supported by the Straico image generation endpoint.
+///
+/// # Variants
+///
+/// * `Square` - Square images (e.g. 1024x1024)
+/// * `Landscape` - Wide images (e.g. 1792x1024)
+/// * `Portrait` - Tall images (e.g. 1024x1792)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageSize {
+    Square,
+    Landscape,
+    Portrait,
+}
+
+impl AsRef<str> for ImageSize {
+    /// Converts the image format into the value expected by the API
+    fn as_ref(&self) -> &str {
+        match self {
+            ImageSize::Square => "square",
+            ImageSize::Landscape => "landscape",
+            ImageSize::Portrait => "portrait",
+        }
+    }
+}
+
+impl FromStr for ImageSize {
+    type Err = StraicoError;
+
+    /// Parses an image format from its name or from `WIDTHxHEIGHT` dimensions.
+    ///
+    /// Dimensions are classified by their aspect ratio, so OpenAI sizes such as
+    /// "1024x1024", "1792x1024" and "1024x1792" map to square, landscape and portrait.
+    ///
+    /// # Arguments
+    ///
+    /// * `s` - A format name ("square", "landscape", "portrait") or dimensions
+    ///
+    /// # Returns
+    ///
+    /// The matching `ImageSize`, or `StraicoError::InvalidRequest` if it cannot be parsed
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || StraicoError::InvalidRequest(format!("unsupported image size '{}'", s));
+        match s.trim().to_lowercase().as_str() {
+            "square" => Ok(ImageSize::Square),
+            "landscape" => Ok(ImageSize::Landscape),
+            "portrait" => Ok(ImageSize::Portrait),
+            dimensions => {
+                let (width, height) = dimensions.split_once('x').ok_or_else(invalid)?;
+                let width: u32 = width.trim().parse().map_err(|_| invalid())?;
+                let height: u32 = height.trim().parse().map_err(|_| invalid())?;
+                Ok(match width.cmp(&height) {
+                    std::cmp::Ordering::Equal => ImageSize::Square,
+                    std::cmp::Ordering::Greater => ImageSize::Landscape,
+                    std::cmp::Ordering::Less => ImageSize::Portrait,
+                })
+            }
+        }
+    }
+}
+
 /// A builder pattern implementation for constructing `ImageRequest` objects.
 ///
 /// # Type Parameters
@@ -97,6 +159,7 @@ impl ImageRequest {
     /// # Returns
     ///
     /// A new `ImageRequestBuilder` instance with no fields set.
+    #[allow(clippy::new_ret_no_self)]
     pub fn new() -> ImageRequestBuilder<ModelNotSet, DescriptionNotSet, SizeNotSet, VariationsNotSet>
     {
         ImageRequestBuilder::default()
@@ -172,6 +235,35 @@ impl<T, U, V> ImageRequestBuilder<T, U, SizeNotSet, V> {
     }
 }
 
+impl<T, U, V> ImageRequestBuilder<T, U, SizeNotSet, V> {
+    /// Requests square images, see `ImageSize::Square`.
+    ///
+    /// # Returns
+    ///
+    /// A new `ImageRequestBuilder` with the size field set and all other fields preserved
+    pub fn square(self) -> ImageRequestBuilder<T, U, SizeSet, V> {
+        self.size(ImageSize::Square.as_ref())
+    }
+
+    /// Requests landscape images, see `ImageSize::Landscape`.
+    ///
+    /// # Returns
+    ///
+    /// A new `ImageRequestBuilder` with the size field set and all other fields preserved
+    pub fn landscape(self) -> ImageRequestBuilder<T, U, SizeSet, V> {
+        self.size(ImageSize::Landscape.as_ref())
+    }
+
+    /// Requests portrait images, see `ImageSize::Portrait`.
+    ///
+    /// # Returns
+    ///
+    /// A new `ImageRequestBuilder` with the size field set and all other fields preserved
+    pub fn portrait(self) -> ImageRequestBuilder<T, U, SizeSet, V> {
+        self.size(ImageSize::Portrait.as_ref())
+    }
+}
+
 impl<T, U, V> ImageRequestBuilder<T, U, V, VariationsNotSet> {
     /// Sets the number of image variations for the image generation request.
     ///
@@ -215,6 +307,16 @@ impl ImageRequestBuilder<ModelSet, DescriptionSet, SizeSet, VariationsSet> {
 }
 
 impl ImageRequest {
+    /// Replaces the model of the request, e.g. to send it to a cheaper model.
+    ///
+    /// # Arguments
+    ///
+    /// * `model` - The identifier of the model to use
+    #[cfg(feature = "model")]
+    pub(crate) fn set_model(&mut self, model: &str) {
+        self.model = model.into();
+    }
+
     /// Returns a reference to the AI model identifier string.
     ///
     /// This getter method provides read-only access to the model field,
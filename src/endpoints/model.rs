@@ -1,4 +1,4 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Represents the root data structure containing collections of chat and image model configurations.
 ///
@@ -47,7 +47,7 @@ pub struct ChatModelMetadata {
 /// This struct contains:
 /// * `coins` - The number of coins charged for using this chat model
 /// * `words` - The number of words provided per coin spent
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ChatPricing {
     coins: f32,
     words: u8,
@@ -72,7 +72,7 @@ pub struct ImageModel {
 /// * `square` - Pricing for square format images
 /// * `landscape` - Pricing for landscape format images
 /// * `portrait` - Pricing for portrait format images
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ImagePricing {
     square: SizePricing,
     landscape: SizePricing,
@@ -84,7 +84,7 @@ pub struct ImagePricing {
 /// This struct contains:
 /// * `coins` - The number of coins charged for this image size format
 /// * `size` - The dimensions of the image in "width x height" format
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct SizePricing {
     coins: u8,
     size: String,
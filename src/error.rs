@@ -0,0 +1,161 @@
+use crate::validation::Violation;
+use reqwest::StatusCode;
+use std::fmt::{self, Display};
+
+/// Represents the ways a request to the Straico API can fail
+///
+/// Each variant corresponds to a distinct failure kind so callers can branch on it,
+/// e.g. retrying transport failures while surfacing insufficient coins to the user.
+///
+/// # Variants
+/// * `Transport` - The request could not be sent or the response could not be read
+/// * `Io` - A local I/O error, e.g. while reading a file to upload
+/// * `Status` - The API answered with a non-success HTTP status code
+/// * `Decode` - The response body was not valid JSON of the expected shape
+/// * `Api` - The API reported an error with `success: false`
+/// * `InsufficientCoins` - The account does not hold enough coins for the request
+/// * `BudgetExceeded` - The request would exceed a spending limit set on the client side
+/// * `UnexpectedResponse` - The response was successful but carried unexpected data
+/// * `InvalidRequest` - The request was rejected locally before being sent
+/// * `Config` - The client configuration is invalid, e.g. a malformed template file
+/// * `InvalidToolCall` - The model wrote a tool call that could not be parsed
+/// * `InvalidOutput` - The model answer does not satisfy the constraints of the request
+#[derive(Debug)]
+pub enum StraicoError {
+    /// The HTTP request could not be sent or the response body could not be read
+    Transport(reqwest::Error),
+    /// A local I/O error occurred while preparing the request
+    Io(std::io::Error),
+    /// The API answered with a non-success HTTP status code
+    Status {
+        /// The HTTP status code returned by the API
+        status: StatusCode,
+        /// The raw response body
+        body: String,
+    },
+    /// The response body could not be decoded
+    Decode {
+        /// The underlying JSON error
+        source: serde_json::Error,
+        /// The raw response body that failed to decode
+        body: String,
+    },
+    /// The API reported an error message with `success: false`
+    Api(String),
+    /// The API refused the request because the account ran out of coins
+    InsufficientCoins(String),
+    /// The request was refused locally because it would exceed a coin budget
+    BudgetExceeded(String),
+    /// The response did not contain the expected kind of data
+    UnexpectedResponse(&'static str),
+    /// The request is invalid and was not sent to the API
+    InvalidRequest(String),
+    /// The configuration is invalid
+    Config(String),
+    /// The model wrote a tool call that could not be parsed, holds the raw call text
+    InvalidToolCall(String),
+    /// The model answer does not satisfy the constraints of the request, e.g. a required
+    /// tool call is missing
+    InvalidOutput(Vec<Violation>),
+}
+
+impl StraicoError {
+    /// Builds an error from a message reported by the API
+    ///
+    /// Messages mentioning coins are classified as `InsufficientCoins`, every other
+    /// message becomes an `Api` error.
+    ///
+    /// # Arguments
+    /// * `message` - The error message returned in the `error` field of the response
+    ///
+    /// # Returns
+    /// The classified `StraicoError`
+    pub(crate) fn from_api_message(message: String) -> Self {
+        if message.to_lowercase().contains("coin") {
+            StraicoError::InsufficientCoins(message)
+        } else {
+            StraicoError::Api(message)
+        }
+    }
+
+    /// Returns the HTTP status code associated with this error, if any
+    ///
+    /// # Returns
+    /// The status code for `Status` errors and transport errors carrying a status, `None` otherwise
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            StraicoError::Status { status, .. } => Some(*status),
+            StraicoError::Transport(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw response body associated with this error, if any
+    ///
+    /// # Returns
+    /// The body for `Status` and `Decode` errors, `None` otherwise
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            StraicoError::Status { body, .. } | StraicoError::Decode { body, .. } => Some(body),
+            _ => None,
+        }
+    }
+}
+
+impl Display for StraicoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StraicoError::Transport(e) => write!(f, "transport error: {}", e),
+            StraicoError::Io(e) => write!(f, "I/O error: {}", e),
+            StraicoError::Status { status, body } => {
+                write!(f, "API returned status {}: {}", status, body)
+            }
+            StraicoError::Decode { source, body } => {
+                write!(f, "failed to decode API response ({}): {}", source, body)
+            }
+            StraicoError::Api(message) => write!(f, "API error: {}", message),
+            StraicoError::InsufficientCoins(message) => {
+                write!(f, "insufficient coins: {}", message)
+            }
+            StraicoError::BudgetExceeded(message) => write!(f, "budget exceeded: {}", message),
+            StraicoError::UnexpectedResponse(expected) => {
+                write!(f, "unexpected API response, expected {}", expected)
+            }
+            StraicoError::InvalidRequest(message) => write!(f, "invalid request: {}", message),
+            StraicoError::Config(message) => write!(f, "invalid configuration: {}", message),
+            StraicoError::InvalidToolCall(call) => {
+                write!(f, "model returned an invalid tool call: {}", call)
+            }
+            StraicoError::InvalidOutput(violations) => {
+                write!(f, "model answer does not satisfy the request")?;
+                for (i, violation) in violations.iter().enumerate() {
+                    write!(f, "{} {}", if i == 0 { ":" } else { ";" }, violation)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for StraicoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StraicoError::Transport(e) => Some(e),
+            StraicoError::Io(e) => Some(e),
+            StraicoError::Decode { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for StraicoError {
+    fn from(value: reqwest::Error) -> Self {
+        StraicoError::Transport(value)
+    }
+}
+
+impl From<std::io::Error> for StraicoError {
+    fn from(value: std::io::Error) -> Self {
+        StraicoError::Io(value)
+    }
+}
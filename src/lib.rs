@@ -1,13 +1,23 @@
+#[cfg(feature = "model")]
+pub mod budget;
 pub mod chat;
 pub mod client;
 pub mod endpoints;
+pub mod error;
+pub mod limit;
+pub mod retry;
+pub mod validation;
+
+/// The root URL of the public Straico API, used when no other base URL is configured
+pub const DEFAULT_BASE_URL: &str = "https://api.straico.com";
 
 /// Represents endpoints for GET requests to the Straico API
 ///
 /// # Variants
 /// * `User` - Endpoint for user information
 /// * `Models` - Endpoint for available models
-enum GetEndpoint {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum GetEndpoint {
     User,
     Models,
 }
@@ -18,35 +28,109 @@ enum GetEndpoint {
 /// * `Image` - Endpoint for image generation
 /// * `Completion` - Endpoint for prompt completion
 /// * `File` - Endpoint for file uploads
-enum PostEndpoint {
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PostEndpoint {
     Image,
     Completion,
     File,
 }
 
+/// Represents any endpoint of the Straico API, e.g. to configure limits per endpoint
+///
+/// Endpoints are named "completion", "image", "file", "models" and "user" when parsed from
+/// or displayed as strings.
+///
+/// # Variants
+/// * `Get` - An endpoint for GET requests
+/// * `Post` - An endpoint for POST requests
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    Get(GetEndpoint),
+    Post(PostEndpoint),
+}
+
+impl From<GetEndpoint> for Endpoint {
+    fn from(value: GetEndpoint) -> Self {
+        Endpoint::Get(value)
+    }
+}
+
+impl From<PostEndpoint> for Endpoint {
+    fn from(value: PostEndpoint) -> Self {
+        Endpoint::Post(value)
+    }
+}
+
+impl Endpoint {
+    /// Every endpoint of the API
+    pub const ALL: [Endpoint; 5] = [
+        Endpoint::Post(PostEndpoint::Completion),
+        Endpoint::Post(PostEndpoint::Image),
+        Endpoint::Post(PostEndpoint::File),
+        Endpoint::Get(GetEndpoint::Models),
+        Endpoint::Get(GetEndpoint::User),
+    ];
+
+    /// Returns the short name of the endpoint, e.g. "completion"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Endpoint::Post(PostEndpoint::Completion) => "completion",
+            Endpoint::Post(PostEndpoint::Image) => "image",
+            Endpoint::Post(PostEndpoint::File) => "file",
+            Endpoint::Get(GetEndpoint::Models) => "models",
+            Endpoint::Get(GetEndpoint::User) => "user",
+        }
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Endpoint {
+    type Err = String;
+
+    /// Parses the short name of an endpoint, e.g. "completion"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Endpoint::ALL
+            .into_iter()
+            .find(|endpoint| endpoint.name().eq_ignore_ascii_case(s.trim()))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Endpoint::ALL.iter().map(Endpoint::name).collect();
+                format!(
+                    "unknown endpoint '{}', expected one of: {}",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
 impl AsRef<str> for GetEndpoint {
-    /// Converts an endpoint enum variant into its corresponding URL string
+    /// Converts an endpoint enum variant into its path relative to the API root
     ///
     /// # Returns
-    /// A string slice containing the full URL for the endpoint
+    /// A string slice containing the endpoint path, without a leading slash
     fn as_ref(&self) -> &str {
         match self {
-            GetEndpoint::User => "https://api.straico.com/v0/user",
-            GetEndpoint::Models => "https://api.straico.com/v1/models",
+            GetEndpoint::User => "v0/user",
+            GetEndpoint::Models => "v1/models",
         }
     }
 }
 
 impl AsRef<str> for PostEndpoint {
-    /// Converts a PostEndpoint enum variant into its corresponding URL string
+    /// Converts a PostEndpoint enum variant into its path relative to the API root
     ///
     /// # Returns
-    /// A string slice containing the full URL for the endpoint
+    /// A string slice containing the endpoint path, without a leading slash
     fn as_ref(&self) -> &str {
         match self {
-            PostEndpoint::Image => "https://api.straico.com/v0/image/generation",
-            PostEndpoint::Completion => "https://api.straico.com/v1/prompt/completion",
-            PostEndpoint::File => "https://api.straico.com/v0/file/upload",
+            PostEndpoint::Image => "v0/image/generation",
+            PostEndpoint::Completion => "v1/prompt/completion",
+            PostEndpoint::File => "v0/file/upload",
         }
     }
 }
@@ -0,0 +1,263 @@
+use crate::Endpoint;
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// A rate of requests, enforced as a token bucket
+///
+/// The bucket holds up to `burst` tokens, each request takes one, and tokens are added back
+/// at `requests` per `period`. Requests finding the bucket empty wait for the next token.
+///
+/// Rates can be parsed from strings such as "60/min", "5/s" or "1000/hour".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    requests: u32,
+    period: Duration,
+    burst: u32,
+}
+
+impl RateLimit {
+    /// Creates a rate of requests per period, allowing bursts of the same number of requests
+    ///
+    /// # Arguments
+    /// * `requests` - How many requests are allowed per period, at least 1
+    /// * `period` - The period, e.g. `Duration::from_secs(60)`
+    pub fn new(requests: u32, period: Duration) -> Self {
+        let requests = requests.max(1);
+        RateLimit {
+            requests,
+            period,
+            burst: requests,
+        }
+    }
+
+    /// Creates a rate of requests per second
+    pub fn per_second(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(1))
+    }
+
+    /// Creates a rate of requests per minute
+    pub fn per_minute(requests: u32) -> Self {
+        Self::new(requests, Duration::from_secs(60))
+    }
+
+    /// Sets how many requests may be sent at once after a quiet period
+    ///
+    /// # Arguments
+    /// * `burst` - The capacity of the bucket, at least 1
+    pub fn burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+
+    /// Returns the number of tokens added back per second
+    fn tokens_per_second(&self) -> f64 {
+        f64::from(self.requests) / self.period.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}s", self.requests, self.period.as_secs_f64())
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parses a rate such as "60/min", "5/s", "1000/hour" or "10/30s"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            format!(
+                "expected REQUESTS/PERIOD such as 60/min or 5/s, got '{}'",
+                s
+            )
+        };
+        let (requests, period) = s.split_once('/').ok_or_else(error)?;
+        let requests: u32 = requests.trim().parse().map_err(|_| error())?;
+        let period = period.trim();
+        let unit_start = period
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(error)?;
+        let count: u64 = match &period[..unit_start] {
+            "" => 1,
+            count => count.parse().map_err(|_| error())?,
+        };
+        let unit = match &period[unit_start..] {
+            "s" | "sec" | "second" | "seconds" => 1,
+            "m" | "min" | "minute" | "minutes" => 60,
+            "h" | "hour" | "hours" => 3600,
+            "d" | "day" | "days" => 86400,
+            _ => return Err(error()),
+        };
+        if requests == 0 || count == 0 {
+            return Err(error());
+        }
+        Ok(RateLimit::new(requests, Duration::from_secs(count * unit)))
+    }
+}
+
+/// The state of a token bucket
+struct Bucket {
+    limit: RateLimit,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Creates a full bucket
+    fn new(limit: RateLimit) -> Self {
+        Bucket {
+            limit,
+            tokens: f64::from(limit.burst),
+            updated: Instant::now(),
+        }
+    }
+
+    /// Takes a token if one is available
+    ///
+    /// # Returns
+    /// Nothing, or how long to wait for the next token
+    fn take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let rate = self.limit.tokens_per_second();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(f64::from(self.limit.burst));
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+}
+
+/// A rate limit and a concurrency cap applied to a group of requests
+#[derive(Default)]
+struct Limits {
+    bucket: Option<Mutex<Bucket>>,
+    semaphore: Option<Arc<Semaphore>>,
+}
+
+impl Limits {
+    /// Waits until a request may be sent
+    ///
+    /// # Returns
+    /// The concurrency permit, to hold until the response is read, if a cap is set
+    async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        let permit = match &self.semaphore {
+            // The semaphore is never closed
+            Some(semaphore) => semaphore.clone().acquire_owned().await.ok(),
+            None => None,
+        };
+        if let Some(bucket) = &self.bucket {
+            loop {
+                let wait = bucket.lock().unwrap_or_else(|e| e.into_inner()).take();
+                match wait {
+                    Ok(()) => break,
+                    Err(delay) => tokio::time::sleep(delay).await,
+                }
+            }
+        }
+        permit
+    }
+}
+
+/// Limits the rate and the concurrency of the requests of a `StraicoClient`
+///
+/// Limits can be set for all requests together, to stay below the limits of an API key, and
+/// for each endpoint, e.g. to keep long completions from starving model listings. A request
+/// has to satisfy both. Requests above a rate wait for their turn, and requests above a
+/// concurrency cap wait for a running request to finish. Retries count as new requests.
+///
+/// The limiter is shared by every clone of the client it is set on.
+///
+/// ```no_run
+/// use straico::client::StraicoClient;
+/// use straico::limit::{Limiter, RateLimit};
+/// use straico::PostEndpoint;
+///
+/// let client = StraicoClient::builder()
+///     .limiter(
+///         Limiter::new()
+///             .rate_limit(RateLimit::per_minute(120))
+///             .endpoint_max_concurrency(PostEndpoint::Completion, 8),
+///     )
+///     .build();
+/// ```
+#[derive(Default)]
+pub struct Limiter {
+    all: Limits,
+    endpoints: HashMap<Endpoint, Limits>,
+}
+
+impl Limiter {
+    /// Creates a limiter without limits
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the rate of all requests together
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.all.bucket = Some(Mutex::new(Bucket::new(limit)));
+        self
+    }
+
+    /// Sets how many requests may run at the same time, all endpoints together
+    ///
+    /// # Arguments
+    /// * `max` - The number of concurrent requests, at least 1
+    pub fn max_concurrency(mut self, max: usize) -> Self {
+        self.all.semaphore = Some(Arc::new(Semaphore::new(max.max(1))));
+        self
+    }
+
+    /// Sets the rate of the requests to an endpoint
+    ///
+    /// # Arguments
+    /// * `endpoint` - The endpoint, e.g. `PostEndpoint::Completion`
+    /// * `limit` - The rate of requests
+    pub fn endpoint_rate_limit<E: Into<Endpoint>>(mut self, endpoint: E, limit: RateLimit) -> Self {
+        self.endpoints.entry(endpoint.into()).or_default().bucket =
+            Some(Mutex::new(Bucket::new(limit)));
+        self
+    }
+
+    /// Sets how many requests to an endpoint may run at the same time
+    ///
+    /// # Arguments
+    /// * `endpoint` - The endpoint, e.g. `PostEndpoint::Completion`
+    /// * `max` - The number of concurrent requests, at least 1
+    pub fn endpoint_max_concurrency<E: Into<Endpoint>>(mut self, endpoint: E, max: usize) -> Self {
+        self.endpoints.entry(endpoint.into()).or_default().semaphore =
+            Some(Arc::new(Semaphore::new(max.max(1))));
+        self
+    }
+
+    /// Waits until a request to an endpoint may be sent
+    ///
+    /// # Arguments
+    /// * `endpoint` - The endpoint of the request
+    ///
+    /// # Returns
+    /// The permit to hold until the response is read
+    pub(crate) async fn acquire(&self, endpoint: Endpoint) -> Permit {
+        let endpoint = match self.endpoints.get(&endpoint) {
+            Some(limits) => limits.acquire().await,
+            None => None,
+        };
+        let all = self.all.acquire().await;
+        Permit {
+            _permits: [endpoint, all],
+        }
+    }
+}
+
+/// The right to run a request, released when dropped
+pub(crate) struct Permit {
+    _permits: [Option<OwnedSemaphorePermit>; 2],
+}
@@ -1,7 +1,26 @@
 use actix_web::{web, App, HttpResponse, HttpServer};
-use clap::Parser;
+use auth::{Auth, AuthMode};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use proxy_error::ProxyError;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::Duration;
+use straico::budget::Budget;
+use straico::chat::PromptRegistry;
+use straico::endpoints::completion::fallback::Fallback;
+use straico::endpoints::completion::tool_call_parser::InvalidToolCallPolicy;
+use straico::limit::{Limiter, RateLimit};
+use straico::retry::RetryPolicy;
+use straico::Endpoint;
 
+mod attachments;
+mod auth;
+mod connection;
+mod proxy_error;
 mod server;
+mod stream;
 
 #[derive(Parser)]
 #[command(
@@ -22,11 +41,135 @@ struct Cli {
     #[arg(long, env = "STRAICO_API_KEY", hide_env_values = true)]
     api_key: Option<String>,
 
+    /// How callers authenticate: "static" uses --api-key for every request, "passthrough"
+    /// forwards the caller's bearer token to Straico, "keys" accepts only the keys issued
+    /// in --keys-file and uses the Straico key each one maps to
+    #[arg(long, value_enum, default_value = "static")]
+    auth: AuthMode,
+
+    /// Keys file (TOML or JSON) listing the keys issued to callers, for --auth keys
+    #[arg(long, value_name = "PATH", required_if_eq("auth", "keys"))]
+    keys_file: Option<std::path::PathBuf>,
+
+    /// Base URL of the upstream Straico API (alternatively use STRAICO_UPSTREAM_URL env var)
+    #[arg(long, env = "STRAICO_UPSTREAM_URL", default_value = straico::DEFAULT_BASE_URL)]
+    upstream_url: String,
+
+    /// Seconds to cache the upstream model list served at /v1/models
+    #[arg(long, default_value = "300")]
+    models_cache_ttl: u64,
+
+    /// Also list image generation models at /v1/models
+    #[arg(long)]
+    include_image_models: bool,
+
+    /// Template file (TOML or JSON) defining prompt formats and the models they apply to
+    #[arg(long, value_name = "PATH")]
+    templates: Option<std::path::PathBuf>,
+
+    /// Prompt format to use for a model, as MODEL=FORMAT (repeatable), e.g.
+    /// google/gemma-2-27b-it=gemma. Built-in formats: default, anthropic, mistral, llama3,
+    /// command-r, qwen, chatml, gemma, phi, deepseek, plain
+    #[arg(long = "prompt-format", value_name = "MODEL=FORMAT", value_parser = parse_assignment)]
+    prompt_formats: Vec<(String, String)>,
+
+    /// Models to try, in order, when a model fails, refuses to answer, answers nothing or
+    /// reaches the token limit, as MODEL=FALLBACK[,FALLBACK...] (repeatable), e.g.
+    /// openai/gpt-4o=anthropic/claude-3.5-sonnet,openai/gpt-4o-mini
+    #[arg(long = "fallback", value_name = "MODEL=MODELS", value_parser = parse_assignment)]
+    fallbacks: Vec<(String, String)>,
+
+    /// What to do with tool calls the model wrote but that cannot be parsed:
+    /// keep them as text, drop them, or fail the request
+    #[arg(long, value_name = "keep|drop|error", default_value = "keep")]
+    invalid_tool_calls: InvalidToolCallPolicy,
+
+    /// How many times an answer that does not satisfy the request, e.g. missing a tool call
+    /// required by tool_choice, is sent back to the model for correction
+    #[arg(long, default_value = "0")]
+    max_reasks: u32,
+
+    /// How many times a request failing for a transient reason, e.g. a 502 or a reset
+    /// connection, is retried upstream. Completions and image generations cost coins and are
+    /// only retried when Straico did not process them
+    #[arg(long, default_value = "0")]
+    max_retries: u32,
+
+    /// Seconds to wait for each upstream request, including the whole answer, before failing
+    /// with 504 Gateway Timeout, 0 to wait indefinitely
+    #[arg(long, default_value = "600")]
+    upstream_timeout: u64,
+
+    /// Seconds to wait for a connection to the upstream API
+    #[arg(long, default_value = "10")]
+    connect_timeout: u64,
+
+    /// Rate of upstream requests, all callers together, as REQUESTS/PERIOD, or as
+    /// ENDPOINT=REQUESTS/PERIOD for one endpoint (repeatable), e.g. 60/min or completion=5/s.
+    /// Endpoints: completion, image, file, models, user
+    #[arg(long = "rate-limit", value_name = "[ENDPOINT=]RATE", value_parser = parse_scoped::<RateLimit>)]
+    rate_limits: Vec<(Option<Endpoint>, RateLimit)>,
+
+    /// How many upstream requests may run at the same time, as N, or as ENDPOINT=N for one
+    /// endpoint (repeatable), e.g. 16 or completion=4
+    #[arg(long = "max-concurrency", value_name = "[ENDPOINT=]N", value_parser = parse_scoped::<usize>)]
+    max_concurrency: Vec<(Option<Endpoint>, usize)>,
+
+    /// Most coins a single completion or image generation may cost, estimated from the
+    /// model prices, max_tokens and the prompt length
+    #[arg(long, value_name = "COINS")]
+    max_request_cost: Option<f32>,
+
+    /// Most coins all requests together may cost per day (UTC)
+    #[arg(long, value_name = "COINS")]
+    daily_budget: Option<f32>,
+
+    /// Most coins the requests of each caller key may cost per day (UTC), unless the keys
+    /// file sets a daily_budget for the key
+    #[arg(long, value_name = "COINS")]
+    key_daily_budget: Option<f32>,
+
+    /// Check the balance of the Straico account before each paid request and refuse requests
+    /// that would leave fewer coins than this
+    #[arg(long, value_name = "COINS")]
+    min_balance: Option<f32>,
+
+    /// Seconds to cache the balance checked for --min-balance, for each API key
+    #[arg(long, default_value = "10")]
+    balance_cache_ttl: u64,
+
+    /// Cheaper models to send requests to, in order, when they would exceed a budget, as
+    /// MODEL=CHEAPER[,CHEAPER...] (repeatable), e.g. openai/gpt-4o=openai/gpt-4o-mini
+    #[arg(long = "downgrade", value_name = "MODEL=MODELS", value_parser = parse_assignment)]
+    downgrades: Vec<(String, String)>,
+
     /// Enable debug logging of requests and responses
     #[arg(long)]
     debug: bool,
 }
 
+/// Parses a MODEL=VALUE command line argument
+fn parse_assignment(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(model, value)| (model.trim().to_string(), value.trim().to_string()))
+        .filter(|(model, value)| !model.is_empty() && !value.is_empty())
+        .ok_or_else(|| format!("expected MODEL=VALUE, got '{}'", arg))
+}
+
+/// Parses a VALUE or ENDPOINT=VALUE command line argument
+fn parse_scoped<T>(arg: &str) -> Result<(Option<Endpoint>, T), String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let (endpoint, value) = match arg.split_once('=') {
+        Some((endpoint, value)) => (Some(endpoint.parse()?), value),
+        None => (None, arg),
+    };
+    let value = value.trim().parse().map_err(|e: T::Err| e.to_string())?;
+    Ok((endpoint, value))
+}
+
 // pub fn completion_with_key(
 //     api_key: impl Display,
 // ) -> Result<StraicoRequestBuilder<ApiKeySet, CompletionRequest<'a>, CompletionData>> {
@@ -37,13 +180,33 @@ struct Cli {
 /// Represents the application state shared across HTTP request handlers.
 ///
 /// This struct contains all the necessary components for handling requests,
-/// including the Straico API client, authentication key, and debug settings.
-#[derive(Clone)]
+/// including the Straico API client, authentication, and debug settings.
+/// A single instance is shared by every worker so that caches are common to all of them.
 struct AppState {
     /// The Straico API client used for making requests
     client: straico::client::StraicoClient,
-    /// API authentication key for Straico
-    key: String,
+    /// How callers authenticate and which Straico key their requests use
+    auth: Auth,
+    /// HTTP client used to download generated images
+    http: reqwest::Client,
+    /// Cache of the model list served at /v1/models and of the model prices
+    models: server::ModelsCache,
+    /// The inline attachments already uploaded, so they are not uploaded with every turn
+    uploads: attachments::UploadCache,
+    /// Whether image models are listed at /v1/models
+    include_image_models: bool,
+    /// Prompt renderers and the models they are used for
+    prompts: PromptRegistry,
+    /// Fallback chains of the models that have one, starting with the model itself
+    fallbacks: HashMap<String, Fallback>,
+    /// What to do with tool calls that cannot be parsed
+    invalid_tool_calls: InvalidToolCallPolicy,
+    /// How many times an invalid answer is sent back to the model for correction
+    max_reasks: u32,
+    /// Spending limits checked before paid requests, if any
+    budget: Option<Budget>,
+    /// The balances checked before paid requests, if a minimum balance is set
+    balances: Option<server::BalanceCache>,
     /// Flag to enable debug logging of requests/responses
     debug: bool,
 }
@@ -52,26 +215,177 @@ struct AppState {
 async fn main() -> std::io::Result<()> {
     let cli = Cli::parse();
 
-    let api_key = cli.api_key.unwrap();
+    let auth = match cli.auth {
+        AuthMode::Static => match cli.api_key {
+            Some(key) => Auth::Static(key),
+            None => Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "--api-key or STRAICO_API_KEY is required with --auth static",
+                )
+                .exit(),
+        },
+        AuthMode::Passthrough => Auth::Passthrough,
+        AuthMode::Keys => {
+            let path = cli.keys_file.as_deref().expect("clap requires --keys-file");
+            Auth::load_keys(path, cli.api_key.as_deref()).unwrap_or_else(|e| {
+                let message = format!("cannot load {}: {}", path.display(), e);
+                Cli::command()
+                    .error(ErrorKind::InvalidValue, message)
+                    .exit()
+            })
+        }
+    };
+
+    let mut prompts = PromptRegistry::default();
+    if let Some(templates) = &cli.templates {
+        if let Err(e) = prompts.load(templates) {
+            let message = format!("cannot load {}: {}", templates.display(), e);
+            Cli::command()
+                .error(ErrorKind::InvalidValue, message)
+                .exit();
+        }
+    }
+    for (model, format) in &cli.prompt_formats {
+        if let Err(e) = prompts.assign(model.as_str(), format) {
+            Cli::command().error(ErrorKind::InvalidValue, e).exit();
+        }
+    }
+
+    let fallbacks: HashMap<String, Fallback> = cli
+        .fallbacks
+        .iter()
+        .map(|(model, chain)| {
+            let chain = chain.split(',').map(str::trim).filter(|m| !m.is_empty());
+            let models = std::iter::once(model.as_str()).chain(chain);
+            (model.clone(), Fallback::new(models))
+        })
+        .collect();
+
+    let mut budget = Budget::new();
+    if let Some(coins) = cli.max_request_cost {
+        budget = budget.max_per_request(coins);
+    }
+    if let Some(coins) = cli.daily_budget {
+        budget = budget.max_per_day(coins);
+    }
+    if let Some(coins) = cli.key_daily_budget {
+        budget = budget.max_per_key(coins);
+    }
+    if let Some(coins) = cli.min_balance {
+        budget = budget.min_balance(coins);
+    }
+    let mut budgeted = cli.max_request_cost.is_some()
+        || cli.daily_budget.is_some()
+        || cli.key_daily_budget.is_some()
+        || cli.min_balance.is_some();
+    for (key, coins) in auth.key_budgets() {
+        budget = budget.key_limit(key, coins);
+        budgeted = true;
+    }
+    for (model, cheaper) in &cli.downgrades {
+        let cheaper = cheaper.split(',').map(str::trim).filter(|m| !m.is_empty());
+        budget = budget.downgrade(model.as_str(), cheaper);
+    }
+    if !budgeted && !cli.downgrades.is_empty() {
+        eprintln!("warning: --downgrade has no effect without a budget or --min-balance");
+    }
 
     let addr = format!("{}:{}", cli.host, cli.port);
     println!("Starting Straico proxy server...");
     println!("Server is running at http://{}", addr);
+    println!("Forwarding requests to {}", cli.upstream_url);
     println!("Completions endpoint is at /v1/chat/completions");
+    println!("Models endpoint is at /v1/models");
+    println!("Image generation endpoint is at /v1/images/generations");
+    println!("File upload endpoint is at /v1/files");
+    match &auth {
+        Auth::Static(_) => println!("All requests use the configured Straico API key"),
+        Auth::Passthrough => println!("Callers' bearer tokens are forwarded as Straico API keys"),
+        Auth::Keys(keys) => println!("Accepting {} issued API keys", keys.len()),
+    }
+    if budgeted {
+        println!("Paid requests are checked against the coin budget");
+    }
     if cli.debug {
         println!("Debug mode enabled - requests and responses will be logged");
     }
 
+    let mut client = straico::client::StraicoClient::builder()
+        .base_url(cli.upstream_url.as_str())
+        .connect_timeout(Duration::from_secs(cli.connect_timeout));
+    if cli.upstream_timeout > 0 {
+        client = client.timeout(Duration::from_secs(cli.upstream_timeout));
+    }
+    if cli.max_retries > 0 {
+        let policy = RetryPolicy::new()
+            .max_attempts(cli.max_retries.saturating_add(1))
+            .on_retry(|event| {
+                eprintln!(
+                    "warning: attempt {} failed, retrying in {:.1}s: {}",
+                    event.attempt,
+                    event.delay.as_secs_f64(),
+                    event.error
+                )
+            });
+        client = client.retry(policy);
+    }
+    if !cli.rate_limits.is_empty() || !cli.max_concurrency.is_empty() {
+        let mut limiter = Limiter::new();
+        for (endpoint, limit) in &cli.rate_limits {
+            limiter = match endpoint {
+                Some(endpoint) => limiter.endpoint_rate_limit(*endpoint, *limit),
+                None => limiter.rate_limit(*limit),
+            };
+        }
+        for (endpoint, max) in &cli.max_concurrency {
+            limiter = match endpoint {
+                Some(endpoint) => limiter.endpoint_max_concurrency(*endpoint, *max),
+                None => limiter.max_concurrency(*max),
+            };
+        }
+        client = client.limiter(limiter);
+    }
+
+    let state = web::Data::new(AppState {
+        client: client.build(),
+        auth,
+        http: reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(cli.connect_timeout))
+            .build()
+            .expect("TLS backend cannot be initialized"),
+        models: server::ModelsCache::new(Duration::from_secs(cli.models_cache_ttl)),
+        uploads: attachments::UploadCache::new(),
+        include_image_models: cli.include_image_models,
+        prompts,
+        fallbacks,
+        invalid_tool_calls: cli.invalid_tool_calls,
+        max_reasks: cli.max_reasks,
+        budget: budgeted.then_some(budget),
+        balances: cli
+            .min_balance
+            .map(|_| server::BalanceCache::new(Duration::from_secs(cli.balance_cache_ttl))),
+        debug: cli.debug,
+    });
+
     HttpServer::new(move || {
         App::new()
-            .app_data(web::Data::new(AppState {
-                client: straico::client::StraicoClient::new(),
-                key: api_key.clone(),
-                debug: cli.debug,
-            }))
+            .app_data(state.clone())
+            .app_data(
+                web::JsonConfig::default()
+                    .limit(server::MAX_JSON_BYTES)
+                    .error_handler(|error, _| ProxyError::from(error).into()),
+            )
             .service(server::openai_completion)
-            .default_service(web::to(|| HttpResponse::NotFound()))
+            .service(server::openai_models)
+            .service(server::openai_model)
+            .service(server::openai_image)
+            .service(server::openai_file)
+            .default_service(web::to(|| async {
+                HttpResponse::from_error(ProxyError::not_found("unknown endpoint"))
+            }))
     })
+    .on_connect(connection::on_connect)
     .bind(addr)?
     .run()
     .await
@@ -0,0 +1,309 @@
+use actix_multipart::MultipartError;
+use actix_web::error::{JsonPayloadError, ResponseError};
+use actix_web::http::StatusCode;
+use actix_web::HttpResponse;
+use serde::Serialize;
+use std::fmt::{self, Display};
+use straico::error::StraicoError;
+use straico::validation::Violation;
+
+/// An error answered to the clients of the proxy
+///
+/// Errors are rendered as OpenAI error bodies, `{"error": {"message", "type", "param",
+/// "code"}}`, with the status code OpenAI would use for the same failure, so that client
+/// SDKs retry and report errors as they do against OpenAI.
+///
+/// # Fields
+/// * `status` - The HTTP status code of the response
+/// * `body` - The content of the `error` object
+#[derive(Debug)]
+pub struct ProxyError {
+    status: StatusCode,
+    body: ErrorBody,
+}
+
+/// The `error` object of an OpenAI error response
+///
+/// # Fields
+/// * `message` - Human readable description of the error
+/// * `kind` - The error type, e.g. "invalid_request_error" or "rate_limit_error"
+/// * `param` - The request parameter the error relates to, if any
+/// * `code` - A machine readable error code, if any
+/// * `violations` - Extension field listing what an invalid model answer got wrong
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    message: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    param: Option<String>,
+    code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    violations: Vec<Violation>,
+}
+
+impl ProxyError {
+    /// Creates an error
+    ///
+    /// # Arguments
+    /// * `status` - The HTTP status code of the response
+    /// * `kind` - The OpenAI error type
+    /// * `code` - Optional machine readable error code
+    /// * `message` - Human readable description of the error
+    fn new(
+        status: StatusCode,
+        kind: &'static str,
+        code: Option<&'static str>,
+        message: impl Display,
+    ) -> Self {
+        ProxyError {
+            status,
+            body: ErrorBody {
+                message: message.to_string(),
+                kind,
+                param: None,
+                code,
+                violations: Vec::new(),
+            },
+        }
+    }
+
+    /// Creates a 400 error for a request the proxy cannot handle
+    pub fn invalid_request(message: impl Display) -> Self {
+        Self::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_request_error",
+            None,
+            message,
+        )
+    }
+
+    /// Creates a 401 error for a caller without a valid API key
+    pub fn unauthorized(message: impl Display) -> Self {
+        Self::new(
+            StatusCode::UNAUTHORIZED,
+            "authentication_error",
+            Some("invalid_api_key"),
+            message,
+        )
+    }
+
+    /// Creates a 404 error for an unknown resource, such as a model
+    pub fn not_found(message: impl Display) -> Self {
+        Self::new(
+            StatusCode::NOT_FOUND,
+            "invalid_request_error",
+            Some("not_found"),
+            message,
+        )
+    }
+
+    /// Creates a 413 error for a request body or an upload above the size limit
+    pub fn payload_too_large(message: impl Display) -> Self {
+        Self::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "invalid_request_error",
+            Some("payload_too_large"),
+            message,
+        )
+    }
+
+    /// Creates a 499 error for a request whose client disconnected before the answer
+    ///
+    /// The client never receives it, it only shows in logs.
+    pub fn client_closed() -> Self {
+        Self::new(
+            StatusCode::from_u16(499).unwrap_or(StatusCode::BAD_REQUEST),
+            "invalid_request_error",
+            Some("client_closed_request"),
+            "the client closed the connection before the answer was ready",
+        )
+    }
+
+    /// Sets the request parameter the error relates to, e.g. "tool_choice"
+    pub fn with_param(mut self, param: impl Into<String>) -> Self {
+        self.body.param = Some(param.into());
+        self
+    }
+
+    /// Returns the OpenAI error body, e.g. to send it as a server-sent event
+    pub fn body(&self) -> serde_json::Value {
+        serde_json::json!({ "error": &self.body })
+    }
+
+    /// Maps a non-success status code returned by Straico to the error given to clients
+    ///
+    /// Rejected requests, authentication failures, missing coins, rate limits and timeouts
+    /// keep their meaning, any other failure of the upstream API is a bad gateway. The code
+    /// is taken as a number because reqwest and actix-web use different `http` versions.
+    fn from_upstream_status(status: u16, message: impl Display) -> Self {
+        match status {
+            400 | 422 => Self::invalid_request(message),
+            401 | 403 => Self::unauthorized(message),
+            402 => Self::insufficient_quota(message),
+            404 => Self::not_found(message),
+            408 | 504 => Self::timeout(message),
+            429 => Self::rate_limited(message),
+            _ => Self::new(
+                StatusCode::BAD_GATEWAY,
+                "api_error",
+                Some("upstream_error"),
+                message,
+            ),
+        }
+    }
+
+    /// Creates a 402 error for an account without enough coins
+    fn insufficient_quota(message: impl Display) -> Self {
+        Self::new(
+            StatusCode::PAYMENT_REQUIRED,
+            "insufficient_quota",
+            Some("insufficient_quota"),
+            message,
+        )
+    }
+
+    /// Creates a 429 error for requests refused by a rate limit
+    fn rate_limited(message: impl Display) -> Self {
+        Self::new(
+            StatusCode::TOO_MANY_REQUESTS,
+            "rate_limit_error",
+            Some("rate_limit_exceeded"),
+            message,
+        )
+    }
+
+    /// Creates a 504 error for an upstream request that timed out
+    fn timeout(message: impl Display) -> Self {
+        Self::new(
+            StatusCode::GATEWAY_TIMEOUT,
+            "api_error",
+            Some("upstream_timeout"),
+            message,
+        )
+    }
+}
+
+/// Extracts the error message from a Straico error body, e.g. `{"error": "Unauthorized"}`
+fn upstream_message(body: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    let message = value.get("error").or_else(|| value.get("message"))?;
+    Some(match message {
+        serde_json::Value::String(message) => message.clone(),
+        message => message.to_string(),
+    })
+}
+
+impl From<StraicoError> for ProxyError {
+    /// Maps a failure of the Straico client to the error given to clients
+    fn from(error: StraicoError) -> Self {
+        match &error {
+            StraicoError::Transport(e) if e.is_timeout() => Self::timeout(&error),
+            StraicoError::Transport(e) => match e.status() {
+                Some(status) => Self::from_upstream_status(status.as_u16(), &error),
+                None => Self::new(
+                    StatusCode::BAD_GATEWAY,
+                    "api_error",
+                    Some("upstream_unreachable"),
+                    &error,
+                ),
+            },
+            StraicoError::Status { status, body } => Self::from_upstream_status(
+                status.as_u16(),
+                upstream_message(body).unwrap_or_else(|| error.to_string()),
+            ),
+            StraicoError::InsufficientCoins(message) => Self::insufficient_quota(message),
+            StraicoError::BudgetExceeded(message) => Self::new(
+                StatusCode::TOO_MANY_REQUESTS,
+                "insufficient_quota",
+                Some("budget_exceeded"),
+                message,
+            ),
+            StraicoError::Api(message) => {
+                let lower = message.to_lowercase();
+                if lower.contains("rate limit") || lower.contains("too many") {
+                    Self::rate_limited(message)
+                } else if lower.contains("unauthorized") || lower.contains("api key") {
+                    Self::from_upstream_status(401, message)
+                } else {
+                    Self::from_upstream_status(502, message)
+                }
+            }
+            StraicoError::Decode { .. } | StraicoError::UnexpectedResponse(_) => Self::new(
+                StatusCode::BAD_GATEWAY,
+                "api_error",
+                Some("bad_upstream_response"),
+                &error,
+            ),
+            StraicoError::InvalidRequest(message) => Self::invalid_request(message),
+            StraicoError::InvalidToolCall(_) => Self::new(
+                StatusCode::BAD_GATEWAY,
+                "api_error",
+                Some("invalid_tool_call"),
+                &error,
+            ),
+            StraicoError::InvalidOutput(violations) => {
+                let mut proxy_error = Self::new(
+                    StatusCode::BAD_GATEWAY,
+                    "api_error",
+                    Some("invalid_output"),
+                    &error,
+                );
+                proxy_error.body.violations = violations.clone();
+                proxy_error
+            }
+            StraicoError::Io(_) | StraicoError::Config(_) => Self::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "server_error",
+                None,
+                &error,
+            ),
+        }
+    }
+}
+
+impl From<reqwest::Error> for ProxyError {
+    fn from(error: reqwest::Error) -> Self {
+        StraicoError::from(error).into()
+    }
+}
+
+impl From<JsonPayloadError> for ProxyError {
+    /// Maps a request body that is too large or not valid JSON to a client error
+    fn from(error: JsonPayloadError) -> Self {
+        match error {
+            JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+                Self::payload_too_large(error)
+            }
+            error => Self::invalid_request(error),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ProxyError {
+    /// Maps a request body that does not match the expected shape to a client error
+    fn from(error: serde_json::Error) -> Self {
+        Self::invalid_request(error)
+    }
+}
+
+impl From<MultipartError> for ProxyError {
+    fn from(error: MultipartError) -> Self {
+        Self::invalid_request(error)
+    }
+}
+
+impl Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.body.message)
+    }
+}
+
+impl ResponseError for ProxyError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(self.body())
+    }
+}
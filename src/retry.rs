@@ -0,0 +1,252 @@
+use crate::error::StraicoError;
+use rand::Rng;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A callback invoked before each retry
+type RetryHook = Arc<dyn Fn(&RetryEvent) + Send + Sync>;
+
+/// Describes a failed attempt that is about to be retried
+///
+/// # Fields
+/// * `attempt` - The number of the attempt that failed, starting at 1
+/// * `delay` - How long the client waits before the next attempt
+/// * `error` - Why the attempt failed
+pub struct RetryEvent<'e> {
+    pub attempt: u32,
+    pub delay: Duration,
+    pub error: &'e StraicoError,
+}
+
+/// Controls how a `StraicoClient` retries requests that failed for a transient reason
+///
+/// Failed attempts are retried after an exponentially growing delay, optionally
+/// randomized, until `max_attempts` attempts were made. When the API answers with a
+/// `Retry-After` header the delay it asks for is used instead, and the request fails
+/// right away if that delay is longer than `max_backoff`.
+///
+/// Completion and image requests cost coins, and a request that failed after reaching
+/// the API may still have been charged. By default they are therefore only retried when
+/// they certainly were not processed: when the connection could not be established or
+/// the API answered 429 Too Many Requests. `retry_non_idempotent(true)` retries them like
+/// every other request.
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use straico::client::StraicoClient;
+/// use straico::retry::RetryPolicy;
+///
+/// let client = StraicoClient::builder()
+///     .retry(
+///         RetryPolicy::new()
+///             .max_attempts(5)
+///             .initial_backoff(Duration::from_millis(200))
+///             .on_retry(|event| eprintln!("retrying after {:?}: {}", event.delay, event.error)),
+///     )
+///     .build();
+/// ```
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: bool,
+    retryable_statuses: Vec<StatusCode>,
+    retry_non_idempotent: bool,
+    on_retry: Option<RetryHook>,
+}
+
+impl Default for RetryPolicy {
+    /// Creates a policy making up to 3 attempts, starting with a 500ms delay that doubles
+    /// up to 30s, with jitter, retrying statuses 408, 429, 500, 502, 503 and 504
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: true,
+            retryable_statuses: vec![
+                StatusCode::REQUEST_TIMEOUT,
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            retry_non_idempotent: false,
+            on_retry: None,
+        }
+    }
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_attempts", &self.max_attempts)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("multiplier", &self.multiplier)
+            .field("jitter", &self.jitter)
+            .field("retryable_statuses", &self.retryable_statuses)
+            .field("retry_non_idempotent", &self.retry_non_idempotent)
+            .field("on_retry", &self.on_retry.is_some())
+            .finish()
+    }
+}
+
+impl RetryPolicy {
+    /// Creates a policy with the default settings, see `RetryPolicy::default`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a policy that never retries
+    pub fn none() -> Self {
+        Self::default().max_attempts(1)
+    }
+
+    /// Sets how many attempts are made in total, including the first one
+    ///
+    /// # Arguments
+    /// * `max_attempts` - The number of attempts, values below 1 are treated as 1
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the first retry
+    pub fn initial_backoff(mut self, delay: Duration) -> Self {
+        self.initial_backoff = delay;
+        self
+    }
+
+    /// Sets the longest delay between two attempts
+    ///
+    /// Requests asked to wait longer than this with `Retry-After` are not retried.
+    pub fn max_backoff(mut self, delay: Duration) -> Self {
+        self.max_backoff = delay;
+        self
+    }
+
+    /// Sets the factor the delay is multiplied by after each retry
+    ///
+    /// # Arguments
+    /// * `multiplier` - The growth factor, values below 1 are treated as 1
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Sets whether delays are randomized, so that clients failing together do not retry
+    /// together
+    ///
+    /// With jitter the delay is picked at random between half and all of the backoff.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Sets the HTTP status codes answered by the API that are retried
+    pub fn retryable_statuses<I: IntoIterator<Item = StatusCode>>(mut self, statuses: I) -> Self {
+        self.retryable_statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Sets whether requests that cost coins, completions and image generations, are
+    /// retried after failures that may have been charged
+    pub fn retry_non_idempotent(mut self, retry: bool) -> Self {
+        self.retry_non_idempotent = retry;
+        self
+    }
+
+    /// Sets a callback invoked before each retry, e.g. to log it
+    ///
+    /// # Arguments
+    /// * `hook` - Called with the failed attempt, the delay and the error
+    pub fn on_retry<F: Fn(&RetryEvent) + Send + Sync + 'static>(mut self, hook: F) -> Self {
+        self.on_retry = Some(Arc::new(hook));
+        self
+    }
+
+    /// Decides whether a failed attempt is retried
+    ///
+    /// # Arguments
+    /// * `attempt` - The number of the failed attempt, starting at 1
+    /// * `error` - Why the attempt failed
+    /// * `headers` - The response headers, if the API answered
+    /// * `idempotent` - Whether the request can be repeated without being charged twice
+    ///
+    /// # Returns
+    /// The delay to wait before the next attempt, or `None` if the error is final
+    pub(crate) fn retry_delay(
+        &self,
+        attempt: u32,
+        error: &StraicoError,
+        headers: Option<&HeaderMap>,
+        idempotent: bool,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts || !self.is_retryable(error, idempotent) {
+            return None;
+        }
+        if let Some(delay) = headers.and_then(retry_after) {
+            return (delay <= self.max_backoff).then_some(delay);
+        }
+        let exponent = i32::try_from(attempt - 1).unwrap_or(i32::MAX);
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+        let backoff = if self.jitter {
+            rand::thread_rng().gen_range(backoff / 2.0..=backoff)
+        } else {
+            backoff
+        };
+        Some(Duration::from_secs_f64(backoff))
+    }
+
+    /// Reports a retry to the hook, if any
+    pub(crate) fn notify(&self, event: &RetryEvent) {
+        if let Some(hook) = &self.on_retry {
+            hook(event);
+        }
+    }
+
+    /// Returns whether an error is transient
+    ///
+    /// Requests that are not idempotent are only retried when they were not processed.
+    fn is_retryable(&self, error: &StraicoError, idempotent: bool) -> bool {
+        let safe = idempotent || self.retry_non_idempotent;
+        match error {
+            StraicoError::Transport(e) if e.is_connect() => true,
+            StraicoError::Transport(e) => match e.status() {
+                Some(status) => self.is_retryable_status(status, safe),
+                None => safe && (e.is_timeout() || e.is_request() || e.is_body()),
+            },
+            StraicoError::Status { status, .. } => self.is_retryable_status(*status, safe),
+            _ => false,
+        }
+    }
+
+    /// Returns whether a status code is retried
+    fn is_retryable_status(&self, status: StatusCode, safe: bool) -> bool {
+        self.retryable_statuses.contains(&status)
+            && (safe || status == StatusCode::TOO_MANY_REQUESTS)
+    }
+}
+
+/// Reads the delay of a `Retry-After` header given in seconds
+///
+/// HTTP dates are not supported, the backoff of the policy is used for them.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = headers
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(Duration::from_secs(seconds))
+}
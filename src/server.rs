@@ -1,36 +1,67 @@
+use crate::attachments::{file_id, Attachments};
+use crate::auth::ApiKey;
+use crate::connection::until_disconnect;
+use crate::proxy_error::ProxyError;
+use crate::stream::completion_stream;
 use crate::AppState;
-use actix_web::http::StatusCode;
-use actix_web::HttpResponseBuilder;
-use actix_web::{error::ErrorInternalServerError, post, web, Either, Error, HttpResponse};
-#[allow(unused_imports)]
-use futures::{stream, StreamExt};
+use actix_multipart::Multipart;
+use actix_web::{get, post, web, Either, HttpRequest, HttpResponse};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use futures::{future::try_join_all, TryStreamExt};
 use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
-use std::iter::Iterator;
-use straico::chat::{Chat, Tool};
-use straico::endpoints::completion::completion_request::CompletionRequest;
-#[allow(unused_imports)]
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::time::{SystemTime, UNIX_EPOCH};
+use straico::budget::{Pricing, Reservation};
+use straico::chat::{tool_instructions, Chat, ResponseFormat, Tool, ToolChoice};
+use straico::endpoints::completion::completion_request::{
+    CompletionRequest, Prompt, RequestModels,
+};
 use straico::endpoints::completion::completion_response::{
-    Choice, Completion, Message, ToolCall, Usage,
+    Choice, Completion, FunctionData, Message, ToolCall,
 };
+use straico::endpoints::completion::fallback::Fallback;
+use straico::endpoints::image::{ImageRequest, ImageSize};
+use straico::endpoints::model::{ChatPricing, ImagePricing};
+use straico::error::StraicoError;
+use straico::validation::{validate_tool_calls, Violation};
 
 /// Represents a chat completion request in the OpenAI API format
 ///
 /// This struct maps incoming API requests to the internal completion request format,
-/// providing compatibility with OpenAI-style chat completions.
+/// providing compatibility with OpenAI-style chat completions. Every field of the OpenAI
+/// schema is accepted: the legacy `functions` and `function_call` fields are mapped to
+/// tools, `stop` is applied to the answer, and the fields Straico has no equivalent for,
+/// such as `top_p`, `seed`, `user` or `metadata`, are ignored with a warning.
 ///
 /// # Fields
-/// * `model` - The model identifier to use for completion
+/// * `model` - The model identifier to use for completion, or a comma-separated list of models
+/// * `models` - Optional list of models to fan out to, overriding `model`
 /// * `messages` - The chat history and prompt messages
 /// * `max_tokens` - Optional maximum number of tokens to generate
 /// * `temperature` - Optional temperature parameter for controlling randomness
-/// * `_stream` - Optional streaming parameter (currently unused)
+/// * `stream` - Optional flag to stream the response as server-sent events
+/// * `stream_options` - Optional streaming settings, such as whether to send a usage chunk
 /// * `tools` - Optional list of tools available to the model
-#[derive(Deserialize, Serialize, Clone, Debug)]
-#[serde(into = "CompletionRequest")]
+/// * `tool_choice` - Optional control over whether and which tools the model calls
+/// * `parallel_tool_calls` - Optional flag allowing several tool calls in one answer
+/// * `response_format` - Optional format of the answer, such as JSON following a schema
+/// * `functions` - Optional legacy list of functions, used when `tools` is missing
+/// * `function_call` - Optional legacy control over function calls, used when `tool_choice`
+///   is missing
+/// * `stop` - Optional sequences at which the answer is cut
+/// * `display_transcripts` - Optional flag to return the transcripts of attached YouTube videos
+/// * `unsupported` - The other fields, which are ignored
+#[derive(Deserialize, Clone, Debug)]
 struct OpenAiRequest<'a> {
-    /// The model identifier to use for completion (e.g. "gpt-3.5-turbo")
+    /// The model identifier to use for completion (e.g. "gpt-3.5-turbo"),
+    /// several models can be given separated by commas
     model: Cow<'a, str>,
+    /// Extension field listing up to four models to query with a single request
+    models: Option<Vec<Cow<'a, str>>>,
     /// The conversation history and prompt messages
     messages: Chat,
     /// Maximum number of tokens to generate in the completion response
@@ -38,351 +69,1365 @@ struct OpenAiRequest<'a> {
     max_tokens: Option<u32>,
     /// Controls randomness in the response generation (0.0 to 1.0)
     temperature: Option<f32>,
-    /// Whether to stream the response (currently unused)
-    #[allow(dead_code)]
+    /// Whether to stream the response as server-sent events
     stream: Option<bool>,
+    /// Options controlling the streamed response
+    stream_options: Option<StreamOptions>,
     /// List of tools/functions available to the model during completion
     tools: Option<Vec<Tool>>,
+    /// Whether the model may, must or must not call tools, or which function it must call
+    tool_choice: Option<ToolChoice>,
+    /// Whether an answer may hold several tool calls, true by default
+    parallel_tool_calls: Option<bool>,
+    /// The format of the answer: text, a JSON object or JSON following a schema
+    response_format: Option<ResponseFormat>,
+    /// Deprecated list of functions available to the model
+    functions: Option<Vec<LegacyFunction>>,
+    /// Deprecated control over whether and which function the model calls
+    function_call: Option<LegacyFunctionCall>,
+    /// Up to four sequences at which the answer is cut
+    stop: Option<StopSequences>,
+    /// Extension field asking Straico to return the transcripts of attached YouTube videos
+    display_transcripts: Option<bool>,
+    /// Fields without an equivalent in Straico
+    #[serde(flatten)]
+    unsupported: serde_json::Map<String, serde_json::Value>,
 }
 
-impl<'a> From<OpenAiRequest<'a>> for CompletionRequest<'a> {
-    /// Converts an OpenAI-style chat completion request into a CompletionRequest
-    ///
-    /// Takes an OpenAiRequest which contains chat messages, model selection, and optional
-    /// parameters like max_tokens and temperature, and converts it into a CompletionRequest.
-    /// The conversion process handles optional fields by conditionally building the request
-    /// based on which parameters are present.
-    ///
-    /// # Arguments
-    /// * `value` - The OpenAiRequest to convert containing messages and parameters
-    ///
-    /// # Returns
-    /// A CompletionRequest configured with the specified messages and parameters
-    fn from(value: OpenAiRequest<'a>) -> Self {
-        let builder = CompletionRequest::new()
-            .models(value.model.clone())
-            .message(value.messages.to_prompt(value.tools, &value.model));
-        match (value.max_tokens, value.temperature) {
-            (Some(x), Some(y)) => builder.max_tokens(x).temperature(y).build(),
-            (Some(x), None) => builder.max_tokens(x).build(),
-            (None, Some(y)) => builder.temperature(y).build(),
-            (None, None) => builder.build(),
-        }
-    }
+/// Represents an entry of the legacy `functions` field
+#[derive(Deserialize, Clone, Debug)]
+struct LegacyFunction {
+    name: String,
+    description: Option<String>,
+    parameters: Option<serde_json::Value>,
 }
 
-#[derive(Serialize, Debug)]
-pub struct CompletionStream {
-    choices: Vec<ChoiceStream>,
-    object: Box<str>,
-    id: Box<str>,
-    model: Box<str>,
-    created: u64,
-    usage: Usage,
+/// Represents the legacy `function_call` field: "none", "auto" or a named function
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum LegacyFunctionCall {
+    Mode(String),
+    Function { name: String },
 }
-#[derive(Serialize, Debug)]
-pub struct ChoiceStream {
-    index: u8,
-    delta: Delta,
-    finish_reason: Option<Box<str>>,
+
+impl From<LegacyFunction> for Tool {
+    fn from(value: LegacyFunction) -> Self {
+        Tool::Function {
+            name: value.name,
+            description: value.description,
+            parameters: value.parameters,
+        }
+    }
 }
 
-#[derive(Serialize, Debug)]
-pub struct Delta {
-    #[serde(skip_serializing_if = "Option::is_none")]
-    role: Option<Box<str>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    content: Option<Box<str>>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    tool_calls: Option<Vec<ToolCall>>,
-}
-
-pub struct DeltaIterator<
-    T: Iterator<Item = Box<str>>,
-    I: Iterator<Item = Box<str>>,
-    U: Iterator<Item = Vec<ToolCall>>,
-> {
-    role: T,
-    content: Option<I>,
-    tool_calls: Option<U>,
-}
-
-pub struct ChoiceStreamIterator<
-    T: Iterator<Item = Box<str>>,
-    I: Iterator<Item = Box<str>>,
-    U: Iterator<Item = Vec<ToolCall>>,
-> {
-    index: u8,
-    delta: DeltaIterator<T, I, U>,
-    finish_reason: Option<Box<str>>,
-}
-
-pub struct CompletionStreamIterator<
-    T: Iterator<Item = Box<str>>,
-    I: Iterator<Item = Box<str>>,
-    U: Iterator<Item = Vec<ToolCall>>,
-> {
-    choices: Vec<ChoiceStreamIterator<T, I, U>>,
-    object: Box<str>,
-    id: Box<str>,
-    model: Box<str>,
-    created: u64,
-    usage: Usage,
-}
-
-impl IntoIterator for Delta {
-    type Item = Delta;
-    type IntoIter = DeltaIterator<
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Vec<ToolCall>>,
-    >;
-
-    fn into_iter(self) -> Self::IntoIter {
-        DeltaIterator {
-            role: vec![self.role.unwrap()].into_iter(),
-            content: self.content.map(|c| {
-                c.split_whitespace()
-                    .map(Box::from)
-                    .collect::<Vec<Box<_>>>()
-                    .into_iter()
-            }),
-            tool_calls: self.tool_calls.map(|t| vec![t].into_iter()),
-            // tool_calls: match self.tool_calls {
-            //     Some(t) => Some(vec![t].into_iter()),
-            //     None => None,
-            // },
+impl From<LegacyFunctionCall> for ToolChoice {
+    fn from(value: LegacyFunctionCall) -> Self {
+        match value {
+            LegacyFunctionCall::Mode(mode) if mode == "none" => ToolChoice::None,
+            LegacyFunctionCall::Mode(_) => ToolChoice::Auto,
+            LegacyFunctionCall::Function { name } => ToolChoice::Function(name),
         }
     }
 }
 
-impl IntoIterator for ChoiceStream {
-    type Item = ChoiceStream;
-    type IntoIter = ChoiceStreamIterator<
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Vec<ToolCall>>,
-    >;
+/// Represents the `stop` field, a single sequence or a list of sequences
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+enum StopSequences {
+    One(String),
+    Many(Vec<String>),
+}
 
-    fn into_iter(self) -> Self::IntoIter {
-        ChoiceStreamIterator {
-            index: self.index,
-            delta: self.delta.into_iter(),
-            finish_reason: self.finish_reason,
+impl From<StopSequences> for Vec<String> {
+    fn from(value: StopSequences) -> Self {
+        match value {
+            StopSequences::One(stop) => vec![stop],
+            StopSequences::Many(stops) => stops,
         }
     }
 }
 
-impl IntoIterator for CompletionStream {
-    type Item = CompletionStream;
-    type IntoIter = CompletionStreamIterator<
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Vec<ToolCall>>,
-    >;
-
-    fn into_iter(self) -> Self::IntoIter {
-        CompletionStreamIterator {
-            choices: self.choices.into_iter().map(|x| x.into_iter()).collect(),
-            object: self.object,
-            id: self.id,
-            model: self.model,
-            created: self.created,
-            usage: self.usage,
+/// Converts the legacy `function_call` of assistant messages into `tool_calls`
+///
+/// Each call is given an id, which is also set on the `function` message answering it, so
+/// that the prompt pairs each result with its call.
+///
+/// # Arguments
+/// * `request` - The raw chat completion request
+fn upgrade_function_calls(request: &mut serde_json::Value) {
+    let Some(messages) = request
+        .get_mut("messages")
+        .and_then(serde_json::Value::as_array_mut)
+    else {
+        return;
+    };
+    let mut pending: Vec<(String, String)> = Vec::new();
+    for message in messages
+        .iter_mut()
+        .filter_map(serde_json::Value::as_object_mut)
+    {
+        match message.get("role").and_then(serde_json::Value::as_str) {
+            Some("assistant") => {
+                let Some(call) = message.remove("function_call") else {
+                    continue;
+                };
+                if message
+                    .get("tool_calls")
+                    .is_some_and(|calls| !calls.is_null())
+                {
+                    continue;
+                }
+                let Ok(function) = serde_json::from_value::<FunctionData>(call) else {
+                    continue;
+                };
+                let call = ToolCall::function(function);
+                pending.push((call.function_data().name().into(), call.id().into()));
+                message.insert("tool_calls".into(), serde_json::json!([call]));
+            }
+            Some("function") => {
+                let name = message.get("name").and_then(serde_json::Value::as_str);
+                if let Some(i) = pending.iter().position(|(n, _)| Some(n.as_str()) == name) {
+                    let (_, id) = pending.remove(i);
+                    message.entry("tool_call_id").or_insert(id.into());
+                }
+            }
+            _ => {}
         }
     }
 }
 
-impl<I, T, U> Iterator for DeltaIterator<I, T, U>
-where
-    I: Iterator<Item = Box<str>>,
-    T: Iterator<Item = Box<str>>,
-    U: Iterator<Item = Vec<ToolCall>>,
-{
-    type Item = Delta;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let delta = Delta {
-            role: self.role.next(),
-            content: match &mut self.content {
-                Some(c) => c.next(),
-                None => None,
-            },
-            // content: self.content.next(),
-            tool_calls: match &mut self.tool_calls {
-                Some(t) => t.next(),
-                None => None,
-            },
+/// Represents the `stream_options` object of an OpenAI chat completion request
+///
+/// # Fields
+/// * `include_usage` - Whether usage should be sent in a separate chunk before `[DONE]`
+#[derive(Deserialize, Clone, Debug, Default)]
+struct StreamOptions {
+    #[serde(default)]
+    include_usage: bool,
+}
+
+impl OpenAiRequest<'_> {
+    /// Returns the models requested, in order
+    ///
+    /// The `models` extension field takes precedence; otherwise `model` is split on commas.
+    ///
+    /// # Returns
+    /// The trimmed, non-empty model identifiers
+    fn requested_models(&self) -> Vec<Box<str>> {
+        let models: Vec<&str> = match &self.models {
+            Some(models) => models.iter().map(AsRef::as_ref).collect(),
+            None => self.model.split(',').collect(),
         };
-        if delta.content.is_none() && delta.tool_calls.is_none() {
-            None
-        } else {
-            Some(delta)
-        }
+        models
+            .into_iter()
+            .map(str::trim)
+            .filter(|m| !m.is_empty())
+            .map(Box::from)
+            .collect()
     }
 }
 
-impl Iterator
-    for ChoiceStreamIterator<
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Vec<ToolCall>>,
-    >
-{
-    type Item = ChoiceStream;
+/// A completion request ready to be sent upstream
+///
+/// `CompletionRequest` borrows its attachment URLs, so the request is kept here in owned
+/// pieces and built on demand, e.g. inside the future that is streamed to the client. The
+/// chat is kept unrendered so that an answer can be sent back to the model for correction.
+///
+/// # Fields
+/// * `key` - The Straico API key to send the request with, and the caller it is sent for
+/// * `models` - The validated models to query
+/// * `chat` - The conversation
+/// * `tools` - The tools offered to the model
+/// * `instructions` - The system instructions describing the tools and the answer format
+/// * `tool_choice` - Whether and which tools the model must call
+/// * `parallel_tool_calls` - Whether an answer may hold several tool calls
+/// * `response_format` - The format of the answer
+/// * `stop` - Sequences at which the answer is cut
+/// * `max_tokens` - Optional maximum number of tokens to generate
+/// * `temperature` - Optional temperature parameter
+/// * `display_transcripts` - Optional flag to return the transcripts of YouTube videos
+/// * `attachments` - The files and videos attached to the messages
+struct UpstreamRequest {
+    key: ApiKey,
+    models: Vec<Box<str>>,
+    chat: Chat,
+    tools: Vec<Tool>,
+    instructions: String,
+    tool_choice: ToolChoice,
+    parallel_tool_calls: bool,
+    response_format: ResponseFormat,
+    stop: Vec<String>,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    display_transcripts: Option<bool>,
+    attachments: Attachments,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let choice = ChoiceStream {
-            index: self.index,
-            delta: self.delta.next()?,
-            finish_reason: self.finish_reason.clone(),
+impl UpstreamRequest {
+    /// Converts an OpenAI-style chat completion request and its attachments
+    ///
+    /// # Arguments
+    /// * `value` - The OpenAiRequest to convert containing messages and parameters
+    /// * `attachments` - The attachments collected from the messages
+    /// * `key` - The Straico API key to send the request with
+    ///
+    /// # Returns
+    /// The request, or `StraicoError::InvalidRequest` if the number of models is not
+    /// between 1 and 4 or `tool_choice` requires a tool that was not given
+    fn new(
+        value: OpenAiRequest,
+        attachments: Attachments,
+        key: ApiKey,
+    ) -> Result<Self, StraicoError> {
+        let models = value.requested_models();
+        request_models(&models)?;
+        let tools = match (value.tools, value.functions) {
+            (Some(tools), _) => tools,
+            (None, Some(functions)) => functions.into_iter().map(Tool::from).collect(),
+            (None, None) => Vec::new(),
         };
-        Some(choice)
-    }
-}
-
-impl Iterator
-    for CompletionStreamIterator<
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Box<str>>,
-        std::vec::IntoIter<Vec<ToolCall>>,
-    >
-{
-    type Item = CompletionStream;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        let completion = CompletionStream {
-            choices: self
-                .choices
-                .iter_mut()
-                .map(|x| x.next())
-                .collect::<Option<Vec<ChoiceStream>>>()?,
-            object: self.object.clone(),
-            id: self.id.clone(),
-            model: self.model.clone(),
-            created: self.created,
-            usage: self.usage.clone(),
+        let tool_choice = value
+            .tool_choice
+            .or_else(|| value.function_call.map(ToolChoice::from))
+            .unwrap_or_default();
+        match &tool_choice {
+            ToolChoice::Required if tools.is_empty() => {
+                return Err(StraicoError::InvalidRequest(
+                    "tool_choice 'required' needs at least one tool".into(),
+                ))
+            }
+            ToolChoice::Function(name)
+                if !tools
+                    .iter()
+                    .any(|Tool::Function { name: tool, .. }| tool == name) =>
+            {
+                return Err(StraicoError::InvalidRequest(format!(
+                    "tool_choice names the function '{}' which is not among the tools",
+                    name
+                )))
+            }
+            _ => {}
+        }
+        let parallel_tool_calls = value.parallel_tool_calls.unwrap_or(true);
+        let response_format = value.response_format.unwrap_or_default();
+        let mut instructions = tool_instructions(&tools, &tool_choice, parallel_tool_calls);
+        instructions.push_str(&response_format.instructions());
+        Ok(UpstreamRequest {
+            key,
+            models,
+            chat: value.messages,
+            tools,
+            instructions,
+            tool_choice,
+            parallel_tool_calls,
+            response_format,
+            stop: value.stop.map(Vec::from).unwrap_or_default(),
+            max_tokens: value.max_tokens,
+            temperature: value.temperature,
+            display_transcripts: value.display_transcripts,
+            attachments,
+        })
+    }
+
+    /// Builds the `CompletionRequest` for some of the models, borrowing the attachment URLs
+    fn completion_request<'b>(
+        &'b self,
+        models: &'b [Box<str>],
+        prompt: Prompt<'b>,
+    ) -> Result<CompletionRequest<'b>, StraicoError> {
+        let mut builder = CompletionRequest::new()
+            .models(request_models(models)?)
+            .message(prompt);
+        let file_urls: Vec<&str> = self
+            .attachments
+            .file_urls
+            .iter()
+            .map(AsRef::as_ref)
+            .collect();
+        if !file_urls.is_empty() {
+            builder = builder.file_urls(&file_urls);
+        }
+        let youtube_urls: Vec<&str> = self
+            .attachments
+            .youtube_urls
+            .iter()
+            .map(AsRef::as_ref)
+            .collect();
+        if !youtube_urls.is_empty() {
+            builder = builder.youtube_urls(&youtube_urls);
+        }
+        if let Some(display_transcripts) = self.display_transcripts {
+            builder = builder.display_transcripts(display_transcripts);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            builder = builder.max_tokens(max_tokens);
+        }
+        if let Some(temperature) = self.temperature {
+            builder = builder.temperature(temperature);
+        }
+        Ok(builder.build())
+    }
+
+    /// Returns whether the model is offered tools it may call
+    ///
+    /// Without tools, or with `tool_choice` set to "none", answers are not searched for
+    /// tool calls, so that JSON answers are kept as text.
+    fn offers_tools(&self) -> bool {
+        !self.tools.is_empty() && self.tool_choice != ToolChoice::None
+    }
+
+    /// Checks a parsed choice against the tools, the tool choice and the response format of
+    /// the request
+    ///
+    /// When tools are offered, calls to functions other than a named one are dropped, and
+    /// only the first call is kept when parallel tool calls are disabled. The remaining
+    /// calls must name one of the tools and follow its parameters schema. Text answers are
+    /// cut at the first stop sequence, and answers to JSON formats are replaced by the JSON
+    /// value they contain.
+    ///
+    /// # Returns
+    /// The violations found, empty if the choice satisfies the request
+    fn check(&self, choice: &mut Choice) -> Vec<Violation> {
+        let Message::Assistant {
+            content,
+            tool_calls,
+        } = &mut choice.message
+        else {
+            return Vec::new();
         };
-        Some(completion)
+        let mut violations = Vec::new();
+        if self.offers_tools() {
+            violations.extend(self.tool_choice.enforce(tool_calls));
+            if !self.parallel_tool_calls {
+                if let Some(calls) = tool_calls {
+                    calls.truncate(1);
+                }
+            }
+            if let Some(calls) = tool_calls {
+                violations.extend(validate_tool_calls(calls, &self.tools));
+            }
+        }
+        if let Some(end) = content.as_ref().and_then(|text| {
+            self.stop
+                .iter()
+                .filter(|stop| !stop.is_empty())
+                .filter_map(|stop| text.find(stop.as_str()))
+                .min()
+        }) {
+            *content = content.as_ref().map(|text| text[..end].into());
+            if tool_calls.is_none() {
+                choice.finish_reason = "stop".into();
+            }
+        }
+        if tool_calls.is_none() {
+            if let Some(text) = content {
+                match self.response_format.extract(text) {
+                    Ok(answer) => *content = Some(answer.into()),
+                    Err(errors) => violations.extend(errors),
+                }
+            }
+        }
+        violations
     }
 }
 
-impl From<Message> for Delta {
-    fn from(value: Message) -> Self {
-        match value {
-            Message::User { content } => Delta {
-                role: Some("user".into()),
-                content: Some(content.into()),
-                tool_calls: None,
-            },
-            Message::Assistant {
-                content,
-                tool_calls,
-            } => Delta {
-                role: Some("assistant".into()),
-                content,
-                tool_calls,
-            },
-            Message::System { content } => Delta {
-                role: Some("system".into()),
-                content: Some(content),
-                tool_calls: None,
-            },
-            Message::Tool { content } => Delta {
-                role: Some("function".into()),
-                content: Some(content),
-                tool_calls: None,
-            },
+/// Converts model identifiers into the models of a `CompletionRequest`
+///
+/// # Returns
+/// The models, or `StraicoError::InvalidRequest` if there are not between 1 and 4
+fn request_models(models: &[Box<str>]) -> Result<RequestModels<'_>, StraicoError> {
+    let models: Vec<Cow<str>> = models.iter().map(|m| Cow::Borrowed(m.as_ref())).collect();
+    RequestModels::try_from(models)
+}
+
+/// Renders a chat for a model with the renderer registered for it
+fn render(data: &AppState, request: &UpstreamRequest, chat: &Chat, model: &str) -> Prompt<'static> {
+    chat.render_with_instructions(data.prompts.renderer_for(model), &request.instructions)
+}
+
+/// Fetches what the budget needs to approve a request sent with a key
+///
+/// # Returns
+/// The prices of the models, and the coins left on the account when `--min-balance` is set,
+/// both possibly cached
+async fn budget_inputs(
+    data: &AppState,
+    key: &str,
+) -> Result<(Arc<Models>, Option<f32>), StraicoError> {
+    let models = list_models(data, key).await?;
+    let balance = match &data.balances {
+        Some(balances) => Some(balance(balances, data, key).await?),
+        None => None,
+    };
+    Ok((models, balance))
+}
+
+/// Checks a completion against the budget, if one is configured
+///
+/// # Arguments
+/// * `data` - Shared application state containing client and configuration
+/// * `request` - The request holding the parameters and attachments
+/// * `models` - The models requested
+/// * `chat` - The conversation to send
+///
+/// # Returns
+/// * `Result<(Vec<Box<str>>, Option<Reservation>), StraicoError>` - The models to query,
+///   cheaper ones if the budget downgraded the request, and the reservation to settle with
+///   the price of the answer, or the refusal of the budget
+async fn approve_completion<'d>(
+    data: &'d AppState,
+    request: &UpstreamRequest,
+    models: &[Box<str>],
+    chat: &Chat,
+) -> Result<(Vec<Box<str>>, Option<Reservation<'d>>), StraicoError> {
+    let Some(budget) = &data.budget else {
+        return Ok((models.to_vec(), None));
+    };
+    let (listing, balance) = budget_inputs(data, request.key.as_ref()).await?;
+    let prompt = render(data, request, chat, &models[0]);
+    let mut upstream = request.completion_request(models, prompt)?;
+    let reservation = budget.approve_completion(
+        &listing.pricing,
+        request.key.caller(),
+        balance,
+        &mut upstream,
+    )?;
+    let models: Vec<Box<str>> = upstream.get_models().iter().map(Box::from).collect();
+    if let Some(model) = reservation.downgraded_from() {
+        eprintln!(
+            "warning: {} is over budget, sending the request to {}",
+            model, models[0]
+        );
+    }
+    Ok((models, Some(reservation)))
+}
+
+/// Sends a chat upstream for some models and parses the result into the OpenAI format
+///
+/// The chat is rendered with the renderer registered for the first model. When a budget is
+/// configured the request is checked against it first, and may be sent to cheaper models.
+/// When several models were requested their answers are merged into one completion,
+/// with one choice per model in request order, each attributed to its model.
+///
+/// # Arguments
+/// * `data` - Shared application state containing client and configuration
+/// * `request` - The request holding the parameters and attachments
+/// * `models` - The models to query, used to order the choices
+/// * `chat` - The conversation to send
+///
+/// # Returns
+/// * `Result<Completion, StraicoError>` - The parsed completion or error
+async fn send_completion(
+    data: &AppState,
+    request: &UpstreamRequest,
+    models: &[Box<str>],
+    chat: &Chat,
+) -> Result<Completion, StraicoError> {
+    let (models, reservation) = approve_completion(data, request, models, chat).await?;
+    let prompt = render(data, request, chat, &models[0]);
+    let order: Vec<&str> = models.iter().map(AsRef::as_ref).collect();
+    let result = async {
+        data.client
+            .clone()
+            .completion()
+            .bearer_auth(request.key.as_ref())
+            .json(request.completion_request(&models, prompt)?)
+            .send()
+            .await?
+            .get_completion_data()
+    }
+    .await;
+    if let Some(reservation) = reservation {
+        match &result {
+            Ok(completion_data) => reservation.settle(completion_data.overall_price().total()),
+            Err(error) => reservation.fail(error),
         }
     }
+    let completion_data = result?;
+    let response = completion_data
+        .into_merged_completion(&order)
+        .ok_or(StraicoError::UnexpectedResponse("at least one completion"))?;
+
+    if data.debug {
+        eprintln!("\n\n===== Received response: =====");
+        eprintln!(
+            "\n{}",
+            serde_json::to_string_pretty(&response).unwrap_or_default()
+        );
+    }
+
+    response.parse_with(data.invalid_tool_calls, request.offers_tools())
 }
 
-impl From<Choice> for ChoiceStream {
-    fn from(value: Choice) -> Self {
-        Self {
-            index: value.index,
-            delta: value.message.into(),
-            finish_reason: Some(value.finish_reason),
+/// Sends a prompt to the models of a fallback chain, one after the other, until one of them
+/// gives an acceptable answer
+///
+/// The chain is run by `Fallback::run` like the library's `send_with_fallback`, except that
+/// the chat is rendered for each model with its own renderer and checked once parsed. If
+/// every model gives an answer that is not acceptable, e.g. a refusal, the answer of the
+/// first of them is returned.
+///
+/// # Arguments
+/// * `data` - Shared application state containing client and configuration
+/// * `request` - The request holding the parameters and attachments
+/// * `fallback` - The models to try and when to move to the next one
+///
+/// # Returns
+/// * `Result<Completion, StraicoError>` - The completion of the model that answered, or the
+///   error of the last model if none answered
+async fn send_with_fallback(
+    data: &AppState,
+    request: &UpstreamRequest,
+    fallback: &Fallback,
+) -> Result<Completion, StraicoError> {
+    let models = fallback.models();
+    let attempt = |model: &str| {
+        let model = Box::<str>::from(model);
+        async move { send_completion(data, request, &[model], &request.chat).await }
+    };
+    let check = |model: &str, result: &Result<Completion, StraicoError>| {
+        let reason = match result {
+            Ok(completion) => fallback.check(completion)?,
+            Err(error) => fallback.check_error(error)?,
+        };
+        let next = models
+            .iter()
+            .position(|m| m == model)
+            .and_then(|i| models.get(i + 1));
+        match next {
+            Some(next) => eprintln!(
+                "warning: {} did not answer ({}), falling back to {}",
+                model, reason, next
+            ),
+            None => eprintln!("warning: {} did not answer ({})", model, reason),
         }
+        Some(reason)
+    };
+    Ok(fallback.run(attempt, check).await?.into_data())
+}
+
+/// Asks a model again after an answer that did not satisfy the request
+///
+/// The conversation is sent to the model that gave the answer, followed by the answer and
+/// a user message listing the violations.
+///
+/// # Arguments
+/// * `data` - Shared application state containing client and configuration
+/// * `request` - The original request
+/// * `choice` - The answer to correct
+/// * `violations` - What is wrong with the answer
+///
+/// # Returns
+/// * `Result<Completion, ProxyError>` - The new completion, holding a single choice
+async fn reask(
+    data: &AppState,
+    request: &UpstreamRequest,
+    choice: &Choice,
+    violations: &[Violation],
+) -> Result<Completion, ProxyError> {
+    let model = choice
+        .model
+        .clone()
+        .unwrap_or_else(|| request.models[0].clone());
+    let mut feedback = String::from("Your previous answer is invalid:\n");
+    for violation in violations {
+        feedback.push_str(&format!("- {}\n", violation));
+    }
+    feedback.push_str("Answer again, fixing these problems.");
+    let mut chat = request.chat.clone();
+    chat.push(choice.message.clone());
+    chat.push(Message::User {
+        content: feedback.into(),
+    });
+    if data.debug {
+        eprintln!("\n\n===== Asking {} again: =====", model);
+        eprintln!("\n{:#?}", violations);
     }
+    Ok(send_completion(data, request, &[model], &chat).await?)
 }
 
-impl From<Completion> for CompletionStream {
-    fn from(value: Completion) -> Self {
-        Self {
-            choices: value.choices.into_iter().map(Into::into).collect(),
-            object: value.object,
-            id: value.id,
-            model: value.model,
-            created: value.created,
-            usage: value.usage,
+/// Sends a completion request upstream and checks the answers against the request
+///
+/// The chat is rendered with the renderer registered for the first requested model. A single
+/// model with a fallback chain configured is tried along its chain instead, see
+/// `send_with_fallback`. Answers that do not satisfy the request, e.g. missing a required
+/// tool call or not following the requested JSON schema, are sent back to their model with
+/// the violations up to `max_reasks` times.
+///
+/// # Arguments
+/// * `data` - Shared application state containing client and configuration
+/// * `request` - The completion request to forward
+///
+/// # Returns
+/// * `Result<Completion, ProxyError>` - The parsed completion, or a bad gateway error if an
+///   answer still does not satisfy the request
+async fn request_completion(
+    data: web::Data<AppState>,
+    request: UpstreamRequest,
+) -> Result<Completion, ProxyError> {
+    let fallback = match request.models.as_slice() {
+        [model] => data.fallbacks.get(model.as_ref()),
+        _ => None,
+    };
+    let mut completion = match fallback {
+        Some(fallback) => send_with_fallback(&data, &request, fallback).await?,
+        None => send_completion(&data, &request, &request.models, &request.chat).await?,
+    };
+
+    let mut failures: Vec<(usize, Vec<Violation>)> = Vec::new();
+    for (i, choice) in completion.choices.iter_mut().enumerate() {
+        let violations = request.check(choice);
+        if !violations.is_empty() {
+            failures.push((i, violations));
+        }
+    }
+    for _ in 0..data.max_reasks {
+        if failures.is_empty() {
+            break;
+        }
+        let answers =
+            try_join_all(failures.iter().map(|(i, violations)| {
+                reask(&data, &request, &completion.choices[*i], violations)
+            }))
+            .await?;
+        let retried: Vec<usize> = failures.drain(..).map(|(i, _)| i).collect();
+        for (i, answer) in retried.into_iter().zip(answers) {
+            completion.usage = completion.usage.clone() + answer.usage;
+            let Some(mut choice) = answer.choices.into_iter().next() else {
+                continue;
+            };
+            let previous = &completion.choices[i];
+            choice.index = previous.index;
+            choice.model = previous.model.clone();
+            let violations = request.check(&mut choice);
+            if !violations.is_empty() {
+                failures.push((i, violations));
+            }
+            completion.choices[i] = choice;
         }
     }
+    match failures.into_iter().next() {
+        Some((_, violations)) => Err(StraicoError::InvalidOutput(violations).into()),
+        None => Ok(completion),
+    }
 }
 
 /// Handles OpenAI-style chat completion API requests
 ///
 /// This endpoint processes chat completion requests in the OpenAI API format, forwards them to the
-/// underlying completion service, and returns the generated response. It supports debug logging of
-/// requests and responses when enabled.
+/// underlying completion service, and returns the generated response. When streaming is requested
+/// the response is sent as server-sent events, see `completion_stream`. It supports debug logging
+/// of requests and responses when enabled. If the client disconnects before the completion is
+/// received, the upstream request is cancelled.
 ///
 /// # Arguments
+/// * `key` - The Straico API key of the caller, see `ApiKey`
+/// * `http_req` - The HTTP request, used to notice when the client disconnects
 /// * `req` - The incoming chat completion request in OpenAI format
 /// * `data` - Shared application state containing client and configuration
 ///
 /// # Returns
-/// * `Result<impl Responder, Error>` - The completion response or error
+/// * `Result<impl Responder, ProxyError>` - The completion response or error
 #[post("/v1/chat/completions")]
-async fn openai_completion<'a>(
+async fn openai_completion(
+    key: ApiKey,
+    http_req: HttpRequest,
     req: web::Json<serde_json::Value>,
     data: web::Data<AppState>,
-) -> Result<Either<web::Json<Completion>, HttpResponse>, Error> {
-    let req_inner = req.into_inner();
+) -> Result<Either<web::Json<Completion>, HttpResponse>, ProxyError> {
+    let mut req_inner = req.into_inner();
     if data.debug {
         eprintln!("\n\n===== Request recieved: =====");
         eprintln!("\n{}", serde_json::to_string_pretty(&req_inner)?);
     }
-    let client = data.client.clone();
 
+    upgrade_function_calls(&mut req_inner);
     let req_inner_oa: OpenAiRequest = serde_json::from_value(req_inner)?;
-    let stream = req_inner_oa.stream;
-    let response = client
-        .completion()
-        .bearer_auth(&data.key)
-        // .json(req_inner)
-        .json(req_inner_oa)
+    if !req_inner_oa.unsupported.is_empty() {
+        let fields: Vec<&str> = req_inner_oa.unsupported.keys().map(AsRef::as_ref).collect();
+        eprintln!(
+            "warning: ignoring unsupported request fields: {}",
+            fields.join(", ")
+        );
+    }
+    let models = req_inner_oa.requested_models();
+    let model: Box<str> = models.join(",").into();
+    let stream = req_inner_oa.stream.unwrap_or(false);
+    let include_usage = req_inner_oa
+        .stream_options
+        .as_ref()
+        .is_some_and(|o| o.include_usage);
+    let choices = models.len() as u8;
+    let attachments = Attachments::collect(&data, key.as_ref(), &req_inner_oa.messages).await?;
+    if data.debug && !(attachments.file_urls.is_empty() && attachments.youtube_urls.is_empty()) {
+        eprintln!("\n\n===== Attachments: =====");
+        eprintln!("\n{:#?}", attachments);
+    }
+    let request = UpstreamRequest::new(req_inner_oa, attachments, key)?;
+    let debug = data.debug;
+    let completion = until_disconnect(&http_req, debug, request_completion(data, request));
+
+    if stream {
+        Ok(Either::Right(completion_stream(
+            model,
+            choices,
+            include_usage,
+            completion,
+        )))
+    } else {
+        Ok(Either::Left(web::Json(completion.await?)))
+    }
+}
+
+/// Represents a model in the OpenAI `/v1/models` list format
+///
+/// Besides the standard OpenAI fields, the Straico specific limits and pricing are exposed
+/// so that clients can pick models without a separate call to Straico.
+///
+/// # Fields
+/// * `id` - The Straico model identifier (e.g. "openai/gpt-4o")
+/// * `object` - Always "model"
+/// * `created` - Always 0, Straico does not report creation dates
+/// * `owned_by` - The provider prefix of the identifier, or "straico"
+/// * `name` - The display name of the model
+/// * `details` - Chat or image specific limits and pricing
+#[derive(Serialize, Debug)]
+pub struct ModelObject {
+    id: String,
+    object: &'static str,
+    created: u64,
+    owned_by: String,
+    name: String,
+    #[serde(flatten)]
+    details: ModelDetails,
+}
+
+/// Model specific fields exposed alongside the standard OpenAI model fields
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ModelDetails {
+    /// A chat model with its input word limit, output limit and pricing
+    Chat {
+        word_limit: u32,
+        max_output: u32,
+        pricing: ChatPricing,
+    },
+    /// An image model with its per-size pricing
+    Image { pricing: ImagePricing },
+}
+
+impl ModelObject {
+    /// Creates a model object, deriving `owned_by` from the provider prefix of `id`
+    fn new(id: &str, name: &str, details: ModelDetails) -> Self {
+        let owned_by = id.split_once('/').map_or("straico", |(owner, _)| owner);
+        ModelObject {
+            id: id.into(),
+            object: "model",
+            created: 0,
+            owned_by: owned_by.into(),
+            name: name.into(),
+            details,
+        }
+    }
+}
+
+/// The models fetched from Straico
+///
+/// # Fields
+/// * `list` - The model list served at `/v1/models`
+/// * `pricing` - The prices of every chat and image model, used by the budget
+pub struct Models {
+    list: Vec<ModelObject>,
+    pricing: Pricing,
+}
+
+/// Caches the model list served at `/v1/models` and the prices of the models
+///
+/// The Straico model list rarely changes while OpenAI clients often request it on
+/// startup, so it is fetched at most once per `ttl`. Lists are cached for each API key,
+/// so that a key is checked upstream before being served a list, e.g. with passthrough
+/// authentication.
+pub struct ModelsCache {
+    /// How long a fetched list stays valid
+    ttl: Duration,
+    /// The last list fetched with each key and when it was fetched
+    entries: Mutex<HashMap<String, (Instant, Arc<Models>)>>,
+}
+
+impl ModelsCache {
+    /// Creates an empty cache whose entries expire after `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        ModelsCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the list cached for a key if it has not expired
+    fn get(&self, key: &str) -> Option<Arc<Models>> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .get(key)
+            .filter(|(fetched, _)| fetched.elapsed() < self.ttl)
+            .map(|(_, models)| models.clone())
+    }
+
+    /// Stores a list freshly fetched with a key, forgetting the expired ones, and returns it
+    fn set(&self, key: &str, models: Models) -> Arc<Models> {
+        let models = Arc::new(models);
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+            entries.insert(key.to_string(), (Instant::now(), models.clone()));
+        }
+        models
+    }
+}
+
+/// Caches the coins left on the Straico accounts of the API keys
+///
+/// Checking the balance before each paid request would double the upstream requests, so
+/// it is fetched at most once per `ttl` for each key. Requests sent in the meantime are not
+/// deducted, so the minimum balance may be undershot by what they cost.
+pub struct BalanceCache {
+    /// How long a fetched balance stays valid
+    ttl: Duration,
+    /// The last fetched balance of each key and when it was fetched
+    entries: Mutex<HashMap<String, (Instant, f32)>>,
+}
+
+impl BalanceCache {
+    /// Creates an empty cache whose entries expire after `ttl`
+    pub fn new(ttl: Duration) -> Self {
+        BalanceCache {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the cached balance of a key if it has not expired
+    fn get(&self, key: &str) -> Option<f32> {
+        let entries = self.entries.lock().ok()?;
+        entries
+            .get(key)
+            .filter(|(fetched, _)| fetched.elapsed() < self.ttl)
+            .map(|(_, coins)| *coins)
+    }
+
+    /// Stores a freshly fetched balance, forgetting the expired ones
+    fn set(&self, key: &str, coins: f32) {
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|_, (fetched, _)| fetched.elapsed() < self.ttl);
+            entries.insert(key.to_string(), (Instant::now(), coins));
+        }
+    }
+}
+
+/// Returns the coins left on the account of a key, fetching them from Straico if the
+/// cache expired
+async fn balance(balances: &BalanceCache, data: &AppState, key: &str) -> Result<f32, StraicoError> {
+    if let Some(coins) = balances.get(key) {
+        return Ok(coins);
+    }
+    let user = data.client.clone().user().bearer_auth(key).send().await?;
+    let coins = user.get_user()?.get_coins();
+    balances.set(key, coins);
+    Ok(coins)
+}
+
+/// Returns the OpenAI-formatted model list and the prices of the models, fetching them from
+/// Straico if the cache expired
+///
+/// # Arguments
+/// * `data` - Shared application state containing client, cache and configuration
+/// * `key` - The Straico API key to fetch the list with
+///
+/// # Returns
+/// * `Result<Arc<Models>, StraicoError>` - The models or error
+async fn list_models(data: &AppState, key: &str) -> Result<Arc<Models>, StraicoError> {
+    if let Some(models) = data.models.get(key) {
+        return Ok(models);
+    }
+    let model_data = data
+        .client
+        .clone()
+        .models()
+        .bearer_auth(key)
+        .send()
+        .await?
+        .get_models()?;
+
+    let mut list: Vec<ModelObject> = model_data
+        .chat()
+        .iter()
+        .map(|m| {
+            let details = ModelDetails::Chat {
+                word_limit: m.word_limit(),
+                max_output: m.max_output(),
+                pricing: m.pricing().clone(),
+            };
+            ModelObject::new(m.model(), m.name(), details)
+        })
+        .collect();
+    if data.include_image_models {
+        list.extend(model_data.image().iter().map(|m| {
+            let details = ModelDetails::Image {
+                pricing: m.pricing().clone(),
+            };
+            ModelObject::new(m.model(), m.name(), details)
+        }));
+    }
+    let pricing = Pricing::from_models(&model_data);
+    Ok(data.models.set(key, Models { list, pricing }))
+}
+
+/// Lists the available models in the OpenAI `/v1/models` format
+///
+/// # Arguments
+/// * `key` - The Straico API key of the caller, see `ApiKey`
+/// * `data` - Shared application state containing client, cache and configuration
+///
+/// # Returns
+/// * `Result<HttpResponse, ProxyError>` - The model list or error
+#[get("/v1/models")]
+async fn openai_models(key: ApiKey, data: web::Data<AppState>) -> Result<HttpResponse, ProxyError> {
+    let models = list_models(&data, key.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "object": "list",
+        "data": models.list.as_slice(),
+    })))
+}
+
+/// Retrieves a single model in the OpenAI `/v1/models/{model}` format
+///
+/// Straico identifiers contain a slash, so the whole remaining path is used as the id.
+///
+/// # Arguments
+/// * `key` - The Straico API key of the caller, see `ApiKey`
+/// * `model` - The model identifier from the request path
+/// * `data` - Shared application state containing client, cache and configuration
+///
+/// # Returns
+/// * `Result<HttpResponse, ProxyError>` - The model or a 404 error if it is unknown
+#[get("/v1/models/{model:.*}")]
+async fn openai_model(
+    key: ApiKey,
+    model: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ProxyError> {
+    let models = list_models(&data, key.as_ref()).await?;
+    let model = models.list.iter().find(|m| m.id == *model).ok_or_else(|| {
+        ProxyError::not_found(format!("model '{}' does not exist", model)).with_param("model")
+    })?;
+    Ok(HttpResponse::Ok().json(model))
+}
+
+/// Represents an image generation request in the OpenAI API format
+///
+/// # Fields
+/// * `prompt` - The text description of the desired image
+/// * `model` - Optional model identifier, defaults to "openai/dall-e-3"
+/// * `n` - Optional number of images to generate, between 1 and 4
+/// * `size` - Optional OpenAI size (e.g. "1792x1024") or Straico format name
+/// * `response_format` - Optional "url" (default) or "b64_json"
+#[derive(Deserialize, Debug)]
+struct OpenAiImageRequest {
+    /// The text description of the desired image
+    prompt: String,
+    /// The model identifier to use for generation
+    model: Option<String>,
+    /// The number of images to generate
+    n: Option<u8>,
+    /// The requested image dimensions or format
+    size: Option<String>,
+    /// Whether to return image URLs or base64 encoded image data
+    response_format: Option<String>,
+}
+
+/// Represents a single generated image in the OpenAI response format
+///
+/// Exactly one of `url` and `b64_json` is set, depending on the requested format.
+#[derive(Serialize, Debug)]
+struct OpenAiImage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    b64_json: Option<String>,
+}
+
+/// Downloads an image and returns its content encoded as base64
+async fn download_base64(http: &reqwest::Client, url: &str) -> Result<String, ProxyError> {
+    let bytes = http
+        .get(url)
         .send()
         .await
-        .map_err(|e| ErrorInternalServerError(e))?
-        .get_completion()
-        .map_err(|e| ErrorInternalServerError(e))?;
+        .and_then(reqwest::Response::error_for_status)?
+        .bytes()
+        .await?;
+    Ok(BASE64.encode(bytes))
+}
 
+/// Handles OpenAI-style image generation requests
+///
+/// Maps the OpenAI `prompt`, `n`, `size` and `model` parameters onto an `ImageRequest`.
+/// OpenAI sizes are mapped to the square, landscape or portrait format by aspect ratio,
+/// and model names without a provider prefix (e.g. "dall-e-3") are assumed to be OpenAI's.
+/// With `response_format: "b64_json"` the generated images are downloaded and inlined.
+/// If the client disconnects before the images are received, the upstream request is
+/// cancelled.
+///
+/// # Arguments
+/// * `key` - The Straico API key of the caller, see `ApiKey`
+/// * `http_req` - The HTTP request, used to notice when the client disconnects
+/// * `req` - The incoming image generation request in OpenAI format
+/// * `data` - Shared application state containing client and configuration
+///
+/// # Returns
+/// * `Result<HttpResponse, ProxyError>` - The generated images or error
+#[post("/v1/images/generations")]
+async fn openai_image(
+    key: ApiKey,
+    http_req: HttpRequest,
+    req: web::Json<OpenAiImageRequest>,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ProxyError> {
+    let req = req.into_inner();
     if data.debug {
-        eprintln!("\n\n===== Received response: =====");
-        eprintln!("\n{}", serde_json::to_string_pretty(&response)?);
-    }
-
-    let parsed_response = response.parse().map_err(|e| ErrorInternalServerError(e))?;
-
-    match stream {
-        Some(true) => {
-            let i = CompletionStream::from(parsed_response);
-            let stream = stream::iter(i).map(|chunk| {
-                let json = serde_json::to_string(&chunk).unwrap();
-                Ok::<_, actix_web::Error>(web::Bytes::from(format!("data: {}\n\n", json)))
-            });
-            let end_stream = stream::once(async {
-                Ok::<_, actix_web::Error>(web::Bytes::from("data: [DONE]\n\n"))
-            });
-            let final_stream = stream.chain(end_stream);
-            Ok(Either::Right(
-                HttpResponseBuilder::new(StatusCode::OK)
-                    .content_type("text/event-stream")
-                    .append_header(("Cache-Control", "no-cache"))
-                    .append_header(("Connection", "keep-alive"))
-                    .streaming(final_stream),
+        eprintln!("\n\n===== Image request recieved: =====");
+        eprintln!("\n{:#?}", req);
+    }
+
+    let variations = req.n.unwrap_or(1);
+    if !(1..=4).contains(&variations) {
+        return Err(ProxyError::invalid_request("n must be between 1 and 4").with_param("n"));
+    }
+    let size = match &req.size {
+        Some(size) => size
+            .parse::<ImageSize>()
+            .map_err(|e| ProxyError::invalid_request(e).with_param("size"))?,
+        None => ImageSize::Square,
+    };
+    let model = match req.model.as_deref() {
+        Some(model) if model.contains('/') => model.to_string(),
+        Some(model) => format!("openai/{}", model),
+        None => String::from("openai/dall-e-3"),
+    };
+    let b64 = match req.response_format.as_deref() {
+        None | Some("url") => false,
+        Some("b64_json") => true,
+        Some(other) => {
+            return Err(ProxyError::invalid_request(format!(
+                "unsupported response_format '{}'",
+                other
             ))
+            .with_param("response_format"))
+        }
+    };
+
+    let mut request = ImageRequest::new()
+        .model(&model)
+        .description(&req.prompt)
+        .size(size.as_ref())
+        .variations(variations)
+        .build();
+    let reservation = match &data.budget {
+        Some(budget) => {
+            let (listing, balance) = budget_inputs(&data, key.as_ref()).await?;
+            let reservation =
+                budget.approve_image(&listing.pricing, key.caller(), balance, &mut request)?;
+            if let Some(model) = reservation.downgraded_from() {
+                eprintln!(
+                    "warning: {} is over budget, sending the request to {}",
+                    model,
+                    request.model()
+                );
+            }
+            Some(reservation)
+        }
+        None => None,
+    };
+    let upstream = data
+        .client
+        .clone()
+        .image()
+        .bearer_auth(key.as_ref())
+        .json(request)
+        .send();
+    let result = until_disconnect(&http_req, data.debug, async {
+        Ok(upstream.await.and_then(|response| response.get_image()))
+    })
+    .await?;
+    if let Some(reservation) = reservation {
+        match &result {
+            Ok(image_data) => reservation.settle(f32::from(image_data.price().total())),
+            Err(error) => reservation.fail(error),
         }
-        _ => Ok(Either::Left(web::Json(parsed_response))),
+    }
+    let image_data = result?;
+
+    let images = if b64 {
+        try_join_all(
+            image_data
+                .images()
+                .iter()
+                .map(|url| download_base64(&data.http, url)),
+        )
+        .await?
+        .into_iter()
+        .map(|b64_json| OpenAiImage {
+            url: None,
+            b64_json: Some(b64_json),
+        })
+        .collect()
+    } else {
+        image_data
+            .images()
+            .iter()
+            .map(|url| OpenAiImage {
+                url: Some(url.clone()),
+                b64_json: None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "created": created,
+        "data": images,
+    })))
+}
+
+/// Largest file accepted by `/v1/files`, uploads are buffered in memory before forwarding
+pub const MAX_UPLOAD_BYTES: usize = 100 * 1024 * 1024;
+
+/// Largest `purpose` accepted by `/v1/files`, well above the longest OpenAI purpose
+const MAX_PURPOSE_BYTES: usize = 64;
+
+/// Most fields accepted in the multipart body of `/v1/files`
+const MAX_UPLOAD_FIELDS: usize = 16;
+
+/// Largest multipart body accepted by `/v1/files`, the largest file and room for the other
+/// fields
+const MAX_UPLOAD_BODY_BYTES: usize = MAX_UPLOAD_BYTES + 64 * 1024;
+
+/// Largest JSON body accepted, enough for chat requests carrying an inline image or file
+/// of the 20 MB OpenAI accepts once base64 encoded
+pub const MAX_JSON_BYTES: usize = 28 * 1024 * 1024;
+
+/// Handles OpenAI-style file uploads
+///
+/// Accepts a multipart body with a single `file` field and an optional `purpose` field,
+/// other fields being skipped, forwards the file to the Straico upload endpoint and returns
+/// an OpenAI file object. The object's
+/// `id` encodes the returned Straico URL, which is also exposed in the extra `url` field.
+/// If the client disconnects before the upload is done, the upstream request is cancelled.
+///
+/// # Arguments
+/// * `key` - The Straico API key of the caller, see `ApiKey`
+/// * `http_req` - The HTTP request, used to notice when the client disconnects
+/// * `payload` - The incoming multipart body
+/// * `data` - Shared application state containing client and configuration
+///
+/// # Returns
+/// * `Result<HttpResponse, ProxyError>` - The file object or error
+#[post("/v1/files")]
+async fn openai_file(
+    key: ApiKey,
+    http_req: HttpRequest,
+    mut payload: Multipart,
+    data: web::Data<AppState>,
+) -> Result<HttpResponse, ProxyError> {
+    let mut file: Option<(String, Option<String>, Vec<u8>)> = None;
+    let mut purpose = String::from("assistants");
+    let mut fields = 0;
+    let mut total = 0;
+    while let Some(mut field) = payload.try_next().await? {
+        fields += 1;
+        if fields > MAX_UPLOAD_FIELDS {
+            return Err(ProxyError::invalid_request(format!(
+                "multipart bodies with more than {} fields are not supported",
+                MAX_UPLOAD_FIELDS
+            )));
+        }
+        let name = field.name().map(str::to_owned);
+        // Unknown fields are read to the end without being kept
+        let limit = match name.as_deref() {
+            Some("file") if file.is_some() => {
+                return Err(
+                    ProxyError::invalid_request("only one 'file' field is allowed")
+                        .with_param("file"),
+                )
+            }
+            Some("file") => Some(MAX_UPLOAD_BYTES),
+            Some("purpose") => Some(MAX_PURPOSE_BYTES),
+            _ => None,
+        };
+        let mut bytes = Vec::new();
+        while let Some(chunk) = field.try_next().await? {
+            total += chunk.len();
+            if total > MAX_UPLOAD_BODY_BYTES {
+                return Err(ProxyError::payload_too_large(format!(
+                    "multipart bodies larger than {} bytes are not supported",
+                    MAX_UPLOAD_BODY_BYTES
+                )));
+            }
+            let Some(limit) = limit else {
+                continue;
+            };
+            if bytes.len() + chunk.len() > limit {
+                return Err(match name.as_deref() {
+                    Some("file") => ProxyError::payload_too_large(format!(
+                        "files larger than {} bytes are not supported",
+                        MAX_UPLOAD_BYTES
+                    )),
+                    _ => ProxyError::invalid_request(format!(
+                        "'purpose' must be at most {} bytes long",
+                        MAX_PURPOSE_BYTES
+                    ))
+                    .with_param("purpose"),
+                });
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        match name.as_deref() {
+            Some("file") => {
+                let filename = field
+                    .content_disposition()
+                    .and_then(|cd| cd.get_filename())
+                    .unwrap_or("upload")
+                    .to_string();
+                let mime_type = field.content_type().map(ToString::to_string);
+                file = Some((filename, mime_type, bytes));
+            }
+            Some("purpose") => purpose = String::from_utf8_lossy(&bytes).into_owned(),
+            _ => {}
+        }
+    }
+    let (filename, mime_type, bytes) = file.ok_or_else(|| {
+        ProxyError::invalid_request("missing 'file' field in multipart body").with_param("file")
+    })?;
+    if data.debug {
+        eprintln!("\n\n===== File upload recieved: =====");
+        eprintln!("\n{} ({} bytes, {:?})", filename, bytes.len(), mime_type);
+    }
+
+    let size = bytes.len();
+    let upstream = data
+        .client
+        .clone()
+        .file()
+        .bearer_auth(key.as_ref())
+        .multipart_bytes(bytes, &filename, mime_type.as_deref())?
+        .send();
+    let file_data = until_disconnect(&http_req, data.debug, async {
+        upstream.await?.get_file().map_err(ProxyError::from)
+    })
+    .await?;
+
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": file_id(&file_data.url),
+        "object": "file",
+        "bytes": size,
+        "created_at": created_at,
+        "filename": filename,
+        "purpose": purpose,
+        "status": "processed",
+        "url": file_data.url,
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+    use straico::endpoints::completion::tool_call_parser::InvalidToolCallPolicy;
+
+    /// Builds the upstream request of an OpenAI request
+    fn upstream_request(request: Value) -> UpstreamRequest {
+        let request: OpenAiRequest = serde_json::from_value(request).unwrap();
+        UpstreamRequest::new(request, Attachments::default(), ApiKey::new("key", "key")).unwrap()
+    }
+
+    /// Parses and checks an answer to a request, as `send_completion` and
+    /// `request_completion` do
+    fn answer(request: &UpstreamRequest, content: &str) -> (Choice, Vec<Violation>) {
+        let completion: Completion = serde_json::from_value(json!({
+            "choices": [{
+                "message": {"role": "assistant", "content": content},
+                "index": 0,
+                "finish_reason": "stop"
+            }],
+            "object": "chat.completion",
+            "id": "cmpl-1",
+            "model": "openai/gpt-4o",
+            "created": 1,
+            "usage": {"prompt_tokens": 1, "completion_tokens": 1, "total_tokens": 2}
+        }))
+        .unwrap();
+        let completion = completion
+            .parse_with(InvalidToolCallPolicy::Error, request.offers_tools())
+            .unwrap();
+        let mut choice = completion.choices.into_iter().next().unwrap();
+        let violations = request.check(&mut choice);
+        (choice, violations)
+    }
+
+    #[test]
+    fn json_answer_without_tools() {
+        let json = r#"{"name": "Paris", "arguments": ["capital", "city"]}"#;
+        let weather = json!([{
+            "type": "function",
+            "function": {"name": "weather", "parameters": {"type": "object"}}
+        }]);
+        let requests = [
+            json!({"model": "openai/gpt-4o", "messages": [{"role": "user", "content": "Hi"}]}),
+            json!({
+                "model": "openai/gpt-4o",
+                "messages": [{"role": "user", "content": "Hi"}],
+                "tools": weather,
+                "tool_choice": "none"
+            }),
+        ];
+        for request in requests {
+            let request = upstream_request(request);
+            for text in [json.to_string(), format!("```json\n{}\n```", json)] {
+                let (choice, violations) = answer(&request, &text);
+                assert!(violations.is_empty(), "{:?}", violations);
+                assert_eq!(choice.finish_reason.as_ref(), "stop");
+                let Message::Assistant {
+                    content,
+                    tool_calls,
+                } = choice.message
+                else {
+                    panic!("not an assistant message");
+                };
+                assert_eq!(content.as_deref(), Some(text.as_str()));
+                assert!(tool_calls.is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn tool_call_with_tools() {
+        let request = upstream_request(json!({
+            "model": "openai/gpt-4o",
+            "messages": [{"role": "user", "content": "Hi"}],
+            "tools": [{
+                "type": "function",
+                "function": {"name": "weather", "parameters": {"required": ["city"]}}
+            }]
+        }));
+        let (choice, violations) = answer(&request, r#"{"name": "weather", "arguments": {}}"#);
+        assert_eq!(choice.finish_reason.as_ref(), "tool_calls");
+        assert_eq!(
+            violations,
+            [Violation::new(
+                "tool_calls/0/function/arguments",
+                "missing required property 'city'"
+            )]
+        );
     }
 }
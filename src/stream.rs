@@ -0,0 +1,290 @@
+use crate::proxy_error::ProxyError;
+use actix_web::{web::Bytes, Error, HttpResponse};
+use futures::{stream, Future};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use straico::endpoints::completion::completion_response::{Completion, Message, ToolCall, Usage};
+use tokio::sync::mpsc;
+use tokio::time::{interval_at, Instant};
+
+/// Interval between SSE keep-alive comments while the upstream completion is pending
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of server-sent events buffered between the producer task and the HTTP response
+const CHANNEL_CAPACITY: usize = 32;
+
+/// Represents a single `chat.completion.chunk` event in the OpenAI streaming format
+///
+/// # Fields
+/// * `id` - Identifier shared by every chunk of the same completion
+/// * `object` - Always "chat.completion.chunk"
+/// * `created` - Unix timestamp of when the stream was started
+/// * `model` - The model identifier the completion was requested for
+/// * `choices` - The deltas carried by this chunk, one per choice index
+/// * `usage` - Token usage, only present on the final chunk when it was requested
+#[derive(Serialize, Debug)]
+pub struct CompletionChunk<'a> {
+    id: &'a str,
+    object: &'static str,
+    created: u64,
+    model: &'a str,
+    choices: Vec<ChoiceChunk>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<Usage>,
+}
+
+/// Represents the delta of a single choice within a streamed chunk
+///
+/// # Fields
+/// * `index` - Zero-based position of the choice this delta belongs to
+/// * `delta` - The incremental message content
+/// * `finish_reason` - Why generation stopped, only set on the last chunk of the choice
+#[derive(Serialize, Debug)]
+pub struct ChoiceChunk {
+    index: u8,
+    delta: Delta,
+    finish_reason: Option<Box<str>>,
+}
+
+/// Represents an incremental message update in a streamed choice
+///
+/// Every field is optional so that a chunk only carries what changed: the role on the
+/// first chunk, content fragments afterwards and tool calls at the end.
+#[derive(Serialize, Debug, Default)]
+pub struct Delta {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content: Option<Box<str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<ToolCallChunk>>,
+}
+
+/// A tool call together with its position, as required in streamed deltas
+#[derive(Serialize, Debug)]
+pub struct ToolCallChunk {
+    index: usize,
+    #[serde(flatten)]
+    call: ToolCall,
+}
+
+/// Splits text into fragments that concatenate back to the exact original text
+///
+/// Each fragment holds a word together with the whitespace that follows it, so newlines,
+/// indentation and code formatting survive the round trip. Leading whitespace is kept
+/// at the start of the first fragment.
+///
+/// # Arguments
+/// * `text` - The text to split
+///
+/// # Returns
+/// An iterator over consecutive, non-empty slices of `text`
+fn split_preserving_whitespace(text: &str) -> impl Iterator<Item = &str> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let word_start = rest
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or(rest.len());
+        let word_end = rest[word_start..]
+            .find(char::is_whitespace)
+            .map_or(rest.len(), |i| word_start + i);
+        let end = rest[word_end..]
+            .find(|c: char| !c.is_whitespace())
+            .map_or(rest.len(), |i| word_end + i);
+        let (fragment, tail) = rest.split_at(end);
+        rest = tail;
+        Some(fragment)
+    })
+}
+
+/// Formats a serializable value as a server-sent `data:` event
+fn event<T: Serialize>(value: &T) -> Bytes {
+    let json = serde_json::to_string(value).unwrap_or_default();
+    Bytes::from(format!("data: {}\n\n", json))
+}
+
+/// Converts a finished completion into the sequence of chunks that follow the role chunk
+///
+/// For every choice this yields its content fragments, then its tool calls, then a chunk
+/// with an empty delta carrying the finish reason. Usage is only sent, in a separate chunk
+/// with no choices, when `include_usage` is requested.
+fn completion_chunks(
+    id: &str,
+    created: u64,
+    model: &str,
+    completion: Completion,
+    include_usage: bool,
+) -> Vec<Bytes> {
+    let chunk = |choices: Vec<ChoiceChunk>, usage: Option<Usage>| CompletionChunk {
+        id,
+        object: "chat.completion.chunk",
+        created,
+        model,
+        choices,
+        usage,
+    };
+    let mut events = Vec::new();
+    let mut finish = Vec::new();
+    for choice in completion.choices {
+        let (content, tool_calls) = match choice.message {
+            Message::Assistant {
+                content,
+                tool_calls,
+            } => (content, tool_calls),
+            _ => (None, None),
+        };
+        for fragment in content
+            .as_deref()
+            .map(split_preserving_whitespace)
+            .into_iter()
+            .flatten()
+        {
+            let delta = Delta {
+                content: Some(fragment.into()),
+                ..Default::default()
+            };
+            events.push(event(&chunk(
+                vec![ChoiceChunk {
+                    index: choice.index,
+                    delta,
+                    finish_reason: None,
+                }],
+                None,
+            )));
+        }
+        if let Some(tool_calls) = tool_calls {
+            let delta = Delta {
+                tool_calls: Some(
+                    tool_calls
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, call)| ToolCallChunk { index, call })
+                        .collect(),
+                ),
+                ..Default::default()
+            };
+            events.push(event(&chunk(
+                vec![ChoiceChunk {
+                    index: choice.index,
+                    delta,
+                    finish_reason: None,
+                }],
+                None,
+            )));
+        }
+        finish.push(ChoiceChunk {
+            index: choice.index,
+            delta: Delta::default(),
+            finish_reason: Some(choice.finish_reason),
+        });
+    }
+    events.push(event(&chunk(finish, None)));
+    if include_usage {
+        events.push(event(&chunk(Vec::new(), Some(completion.usage))));
+    }
+    events
+}
+
+/// Streams a completion to the client as OpenAI-compatible server-sent events
+///
+/// The role-only delta is sent immediately. While the upstream completion is pending,
+/// SSE comments are emitted every `KEEP_ALIVE_INTERVAL` so clients and intermediaries
+/// do not time out. Once the completion arrives its text is streamed in fragments that
+/// preserve the original whitespace, followed by the finish reason, the usage when
+/// requested and `[DONE]`. Every chunk carries a randomly generated completion id.
+/// If the client disconnects the upstream future is dropped.
+///
+/// # Arguments
+/// * `model` - The requested model identifier, reported until the answering model is known
+/// * `choices` - The number of choices to announce in the role chunk
+/// * `include_usage` - Whether usage should be sent in a separate final chunk
+/// * `upstream` - Future resolving to the parsed completion
+///
+/// # Returns
+/// A streaming `HttpResponse` with the `text/event-stream` content type
+pub fn completion_stream<F>(
+    model: Box<str>,
+    choices: u8,
+    include_usage: bool,
+    upstream: F,
+) -> HttpResponse
+where
+    F: Future<Output = Result<Completion, ProxyError>> + 'static,
+{
+    let (tx, rx) = mpsc::channel::<Result<Bytes, Error>>(CHANNEL_CAPACITY);
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let suffix: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(29)
+        .map(char::from)
+        .collect();
+    let id = format!("chatcmpl-{}", suffix);
+
+    actix_web::rt::spawn(async move {
+        let role_chunk = CompletionChunk {
+            id: &id,
+            object: "chat.completion.chunk",
+            created,
+            model: &model,
+            choices: (0..choices)
+                .map(|index| ChoiceChunk {
+                    index,
+                    delta: Delta {
+                        role: Some("assistant"),
+                        ..Default::default()
+                    },
+                    finish_reason: None,
+                })
+                .collect(),
+            usage: None,
+        };
+        if tx.send(Ok(event(&role_chunk))).await.is_err() {
+            return;
+        }
+
+        tokio::pin!(upstream);
+        let mut keep_alive = interval_at(Instant::now() + KEEP_ALIVE_INTERVAL, KEEP_ALIVE_INTERVAL);
+        let result = loop {
+            tokio::select! {
+                result = &mut upstream => break result,
+                _ = keep_alive.tick() => {
+                    if tx.send(Ok(Bytes::from_static(b": keep-alive\n\n"))).await.is_err() {
+                        return;
+                    }
+                }
+                _ = tx.closed() => return,
+            }
+        };
+
+        let events = match result {
+            Ok(completion) => {
+                // A fallback chain may have answered with another model than requested
+                let model = completion.model.clone();
+                completion_chunks(&id, created, &model, completion, include_usage)
+            }
+            Err(e) => vec![event(&e.body())],
+        };
+        for bytes in events {
+            if tx.send(Ok(bytes)).await.is_err() {
+                return;
+            }
+        }
+        let _ = tx.send(Ok(Bytes::from_static(b"data: [DONE]\n\n"))).await;
+    });
+
+    let body = stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    });
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .append_header(("Cache-Control", "no-cache"))
+        .append_header(("Connection", "keep-alive"))
+        .streaming(body)
+}
@@ -0,0 +1,670 @@
+use crate::chat::Tool;
+use crate::endpoints::completion::completion_response::ToolCall;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+
+/// A constraint of a request that a model answer does not satisfy.
+///
+/// Violations are reported to callers as structured errors and can be written back to
+/// the model so it can correct its answer.
+///
+/// # Fields
+/// * `path` - Where the problem is, e.g. "tool_calls" or a JSON pointer such as "/items/0"
+/// * `message` - What is wrong
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub path: String,
+    pub message: String,
+}
+
+impl Violation {
+    /// Creates a violation at a path.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where the problem is, empty for the answer as a whole
+    /// * `message` - What is wrong
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        Violation {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+/// Validates a JSON value against a JSON Schema.
+///
+/// A practical subset of JSON Schema is supported: `type`, `enum`, `const`, `properties`,
+/// `required`, `additionalProperties`, `items`, `minItems`, `maxItems`, `minLength`,
+/// `maxLength`, `pattern`, `minimum`, `maximum`, `exclusiveMinimum`, `exclusiveMaximum`,
+/// `multipleOf`, `allOf`, `anyOf`, `oneOf`, `not` and local `$ref`s such as
+/// "#/$defs/item". Other keywords are ignored. A reference that leads back to itself for
+/// the same value is reported as a violation instead of being followed forever.
+///
+/// # Arguments
+///
+/// * `value` - The value to validate
+/// * `schema` - The schema, e.g. the `parameters` of a tool
+///
+/// # Returns
+///
+/// The violations found, with JSON pointers to the offending values as paths, empty if
+/// the value is valid
+pub fn validate(value: &Value, schema: &Value) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    Validator::new(schema).check(value, schema, "", &mut violations);
+    violations
+}
+
+/// Checks tool calls against the tools offered to the model.
+///
+/// Each call must name one of the tools, and its arguments must follow the `parameters`
+/// schema of that tool, if any.
+///
+/// # Arguments
+///
+/// * `tool_calls` - The tool calls of an answer
+/// * `tools` - The tools offered to the model
+///
+/// # Returns
+///
+/// The violations found, with paths such as "tool_calls/0/function/arguments/city", empty
+/// if every call is valid
+pub fn validate_tool_calls(tool_calls: &[ToolCall], tools: &[Tool]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for (i, call) in tool_calls.iter().enumerate() {
+        let function = call.function_data();
+        let tool = tools
+            .iter()
+            .find(|Tool::Function { name, .. }| name == function.name());
+        match tool {
+            None => {
+                let names: Vec<&str> = tools
+                    .iter()
+                    .map(|Tool::Function { name, .. }| name.as_str())
+                    .collect();
+                violations.push(Violation::new(
+                    format!("tool_calls/{}/function/name", i),
+                    format!(
+                        "unknown function '{}', expected one of: {}",
+                        function.name(),
+                        names.join(", ")
+                    ),
+                ));
+            }
+            Some(Tool::Function {
+                parameters: Some(schema),
+                ..
+            }) => {
+                let path = format!("tool_calls/{}/function/arguments", i);
+                violations.extend(validate(function.arguments(), schema).into_iter().map(
+                    |violation| {
+                        Violation::new(format!("{}{}", path, violation.path), violation.message)
+                    },
+                ));
+            }
+            Some(_) => {}
+        }
+    }
+    violations
+}
+
+/// Validates values against the subschemas of a root schema, resolving references in it
+struct Validator<'s> {
+    root: &'s Value,
+    /// The references being followed, with the paths of the values checked against them
+    refs: Vec<(&'s Value, String)>,
+    /// The compiled `pattern`s of the schema, `None` for invalid ones
+    patterns: HashMap<&'s str, Option<Regex>>,
+}
+
+impl<'s> Validator<'s> {
+    /// Creates a validator for a root schema
+    fn new(root: &'s Value) -> Self {
+        Validator {
+            root,
+            refs: Vec::new(),
+            patterns: HashMap::new(),
+        }
+    }
+
+    /// Checks a value against a schema, pushing the violations found
+    fn check(&mut self, value: &Value, schema: &'s Value, path: &str, out: &mut Vec<Violation>) {
+        let schema = match schema {
+            Value::Bool(false) => {
+                out.push(Violation::new(path, "no value is allowed here"));
+                return;
+            }
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match self.resolve(reference) {
+                // Following a reference again for the same value would never terminate
+                Some(target)
+                    if self.refs.iter().any(|(followed, followed_path)| {
+                        std::ptr::eq(*followed, target) && followed_path == path
+                    }) =>
+                {
+                    out.push(Violation::new(
+                        path,
+                        format!("circular schema reference '{}'", reference),
+                    ))
+                }
+                Some(target) => {
+                    self.refs.push((target, path.to_string()));
+                    self.check(value, target, path, out);
+                    self.refs.pop();
+                }
+                None => out.push(Violation::new(
+                    path,
+                    format!("cannot resolve schema reference '{}'", reference),
+                )),
+            }
+        }
+        if let Some(types) = schema.get("type") {
+            let types: Vec<&str> = match types {
+                Value::String(t) => vec![t],
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                _ => Vec::new(),
+            };
+            if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+                out.push(Violation::new(
+                    path,
+                    format!("expected {}, got {}", types.join(" or "), type_name(value)),
+                ));
+                return;
+            }
+        }
+        if let Some(Value::Array(options)) = schema.get("enum") {
+            if !options.contains(value) {
+                out.push(Violation::new(
+                    path,
+                    format!("expected one of {}", Value::from(options.clone())),
+                ));
+            }
+        }
+        if let Some(expected) = schema.get("const") {
+            if expected != value {
+                out.push(Violation::new(path, format!("expected {}", expected)));
+            }
+        }
+        match value {
+            Value::Object(map) => self.check_object(map, schema, path, out),
+            Value::Array(items) => self.check_array(items, schema, path, out),
+            Value::String(text) => self.check_string(text, schema, path, out),
+            Value::Number(number) => {
+                if let Some(number) = number.as_f64() {
+                    check_number(number, schema, path, out)
+                }
+            }
+            _ => {}
+        }
+        if let Some(Value::Array(schemas)) = schema.get("allOf") {
+            for schema in schemas {
+                self.check(value, schema, path, out);
+            }
+        }
+        if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+            if !schemas
+                .iter()
+                .any(|schema| self.matches(value, schema, path))
+            {
+                out.push(Violation::new(
+                    path,
+                    "does not match any of the allowed schemas",
+                ));
+            }
+        }
+        if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+            let matching = schemas
+                .iter()
+                .filter(|schema| self.matches(value, schema, path))
+                .count();
+            if matching != 1 {
+                out.push(Violation::new(
+                    path,
+                    format!("matches {} of the schemas instead of exactly one", matching),
+                ));
+            }
+        }
+        if let Some(schema) = schema.get("not") {
+            if self.matches(value, schema, path) {
+                out.push(Violation::new(path, "matches a disallowed schema"));
+            }
+        }
+    }
+
+    /// Checks the properties of an object
+    fn check_object(
+        &mut self,
+        map: &Map<String, Value>,
+        schema: &'s Map<String, Value>,
+        path: &str,
+        out: &mut Vec<Violation>,
+    ) {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !map.contains_key(name) {
+                    out.push(Violation::new(
+                        path,
+                        format!("missing required property '{}'", name),
+                    ));
+                }
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (name, value) in map {
+            let path = format!("{}/{}", path, name.replace('~', "~0").replace('/', "~1"));
+            match properties.and_then(|properties| properties.get(name)) {
+                Some(schema) => self.check(value, schema, &path, out),
+                None => match schema.get("additionalProperties") {
+                    Some(Value::Bool(false)) => {
+                        out.push(Violation::new(path, "unexpected property"))
+                    }
+                    Some(schema) => self.check(value, schema, &path, out),
+                    None => {}
+                },
+            }
+        }
+    }
+
+    /// Checks the length and the items of an array
+    fn check_array(
+        &mut self,
+        items: &[Value],
+        schema: &'s Map<String, Value>,
+        path: &str,
+        out: &mut Vec<Violation>,
+    ) {
+        if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+            if (items.len() as u64) < min {
+                out.push(Violation::new(
+                    path,
+                    format!("expected at least {} items", min),
+                ));
+            }
+        }
+        if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+            if items.len() as u64 > max {
+                out.push(Violation::new(
+                    path,
+                    format!("expected at most {} items", max),
+                ));
+            }
+        }
+        if let Some(schema) = schema.get("items") {
+            for (i, item) in items.iter().enumerate() {
+                self.check(item, schema, &format!("{}/{}", path, i), out);
+            }
+        }
+    }
+
+    /// Checks the length and the pattern of a string
+    fn check_string(
+        &mut self,
+        text: &str,
+        schema: &'s Map<String, Value>,
+        path: &str,
+        out: &mut Vec<Violation>,
+    ) {
+        let length = text.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+            if length < min {
+                out.push(Violation::new(
+                    path,
+                    format!("expected at least {} characters", min),
+                ));
+            }
+        }
+        if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+            if length > max {
+                out.push(Violation::new(
+                    path,
+                    format!("expected at most {} characters", max),
+                ));
+            }
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+            let regex = self
+                .patterns
+                .entry(pattern)
+                .or_insert_with(|| Regex::new(pattern).ok());
+            if regex.as_ref().is_some_and(|regex| !regex.is_match(text)) {
+                out.push(Violation::new(
+                    path,
+                    format!("does not match the pattern '{}'", pattern),
+                ));
+            }
+        }
+    }
+
+    /// Returns whether the value at a path satisfies a schema
+    fn matches(&mut self, value: &Value, schema: &'s Value, path: &str) -> bool {
+        let mut violations = Vec::new();
+        self.check(value, schema, path, &mut violations);
+        violations.is_empty()
+    }
+
+    /// Resolves a reference local to the root schema, e.g. "#/$defs/item"
+    fn resolve(&self, reference: &str) -> Option<&'s Value> {
+        self.root.pointer(reference.strip_prefix('#')?)
+    }
+}
+
+/// Checks the bounds of a number
+fn check_number(number: f64, schema: &Map<String, Value>, path: &str, out: &mut Vec<Violation>) {
+    let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+    if let Some(min) = bound("minimum").filter(|min| number < *min) {
+        out.push(Violation::new(path, format!("expected at least {}", min)));
+    }
+    if let Some(max) = bound("maximum").filter(|max| number > *max) {
+        out.push(Violation::new(path, format!("expected at most {}", max)));
+    }
+    if let Some(min) = bound("exclusiveMinimum").filter(|min| number <= *min) {
+        out.push(Violation::new(path, format!("expected more than {}", min)));
+    }
+    if let Some(max) = bound("exclusiveMaximum").filter(|max| number >= *max) {
+        out.push(Violation::new(path, format!("expected less than {}", max)));
+    }
+    if let Some(factor) = bound("multipleOf").filter(|factor| *factor > 0.0) {
+        if (number / factor).fract() != 0.0 {
+            out.push(Violation::new(
+                path,
+                format!("expected a multiple of {}", factor),
+            ));
+        }
+    }
+}
+
+/// Returns whether a value has a JSON Schema type, unknown types match any value
+fn has_type(value: &Value, type_name: &str) -> bool {
+    match type_name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        _ => true,
+    }
+}
+
+/// Returns the JSON Schema type name of a value
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Object(_) => "object",
+        Value::Array(_) => "array",
+        Value::String(_) => "string",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::completion::completion_response::FunctionData;
+    use serde_json::json;
+
+    /// Returns the paths and messages of the violations of a value
+    fn violations(value: Value, schema: Value) -> Vec<String> {
+        validate(&value, &schema)
+            .iter()
+            .map(Violation::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn keywords() {
+        let cases = [
+            (json!({"type": "string"}), json!("a"), vec![]),
+            (
+                json!({"type": "string"}),
+                json!(1),
+                vec!["expected string, got integer"],
+            ),
+            (json!({"type": ["string", "null"]}), json!(null), vec![]),
+            (json!({"type": "integer"}), json!(2.0), vec![]),
+            (
+                json!({"type": "integer"}),
+                json!(2.5),
+                vec!["expected integer, got number"],
+            ),
+            (json!({"enum": ["a", "b"]}), json!("b"), vec![]),
+            (
+                json!({"enum": ["a", "b"]}),
+                json!("c"),
+                vec![r#"expected one of ["a","b"]"#],
+            ),
+            (
+                json!({"required": ["a", "b"]}),
+                json!({"a": 1}),
+                vec!["missing required property 'b'"],
+            ),
+            (
+                json!({"properties": {"a": {}}, "additionalProperties": false}),
+                json!({"a": 1, "b": 2}),
+                vec!["/b: unexpected property"],
+            ),
+            (
+                json!({"additionalProperties": {"type": "number"}}),
+                json!({"a": 1, "b": "2"}),
+                vec!["/b: expected number, got string"],
+            ),
+            (
+                json!({"items": {"type": "integer"}}),
+                json!([1, "2", 3, null]),
+                vec![
+                    "/1: expected integer, got string",
+                    "/3: expected integer, got null",
+                ],
+            ),
+            (json!({"pattern": "^[a-z]+$"}), json!("abc"), vec![]),
+            (
+                json!({"pattern": "^[a-z]+$"}),
+                json!("aBc"),
+                vec!["does not match the pattern '^[a-z]+$'"],
+            ),
+            (
+                json!({"pattern": "("}),
+                json!("invalid patterns are ignored"),
+                vec![],
+            ),
+            (json!({"minLength": 2, "maxLength": 3}), json!("éé"), vec![]),
+            (
+                json!({"minLength": 2}),
+                json!("a"),
+                vec!["expected at least 2 characters"],
+            ),
+            (
+                json!({"minimum": 1, "maximum": 3}),
+                json!(0),
+                vec!["expected at least 1"],
+            ),
+            (
+                json!({"minimum": 1, "maximum": 3}),
+                json!(3.5),
+                vec!["expected at most 3"],
+            ),
+            (
+                json!({"exclusiveMinimum": 1, "exclusiveMaximum": 3}),
+                json!(3),
+                vec!["expected less than 3"],
+            ),
+            (
+                json!({"minItems": 2, "maxItems": 2}),
+                json!([1]),
+                vec!["expected at least 2 items"],
+            ),
+        ];
+        for (schema, value, expected) in cases {
+            assert_eq!(
+                violations(value.clone(), schema.clone()),
+                expected,
+                "{} against {}",
+                value,
+                schema
+            );
+        }
+    }
+
+    #[test]
+    fn nested_paths() {
+        let schema = json!({
+            "properties": {
+                "a/b": {"items": {"required": ["c"]}}
+            }
+        });
+        assert_eq!(
+            violations(json!({"a/b": [{"c": 1}, {}]}), schema),
+            ["/a~1b/1: missing required property 'c'"]
+        );
+    }
+
+    #[test]
+    fn pattern_is_compiled_once() {
+        let schema = json!({"items": {"pattern": "^x"}});
+        let mut validator = Validator::new(&schema);
+        let mut out = Vec::new();
+        validator.check(&json!(["x", "y", "xx"]), &schema, "", &mut out);
+        assert_eq!(validator.patterns.len(), 1);
+        assert_eq!(
+            out,
+            [Violation::new("/1", "does not match the pattern '^x'")]
+        );
+    }
+
+    #[test]
+    fn references() {
+        let schema = json!({
+            "$defs": {"item": {"type": "string"}},
+            "items": {"$ref": "#/$defs/item"}
+        });
+        assert_eq!(
+            violations(json!(["a", 1]), schema),
+            ["/1: expected string, got integer"]
+        );
+        assert_eq!(
+            violations(json!(1), json!({"$ref": "#/$defs/missing"})),
+            ["cannot resolve schema reference '#/$defs/missing'"]
+        );
+    }
+
+    #[test]
+    fn recursive_reference() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "children": {"type": "array", "items": {"anyOf": [{"$ref": "#"}]}}
+            }
+        });
+        let tree = json!({"children": [{"children": [{"children": []}]}]});
+        assert!(violations(tree, schema.clone()).is_empty());
+        let tree = json!({"children": [{"children": [1]}]});
+        assert_eq!(
+            violations(tree, schema),
+            ["/children/0: does not match any of the allowed schemas"]
+        );
+    }
+
+    #[test]
+    fn circular_references() {
+        let schema = json!({
+            "$defs": {"a": {"$ref": "#/$defs/b"}, "b": {"$ref": "#/$defs/a"}},
+            "$ref": "#/$defs/a"
+        });
+        assert_eq!(
+            violations(json!({}), schema),
+            ["circular schema reference '#/$defs/a'"]
+        );
+        assert_eq!(
+            violations(json!(1), json!({"$ref": "#"})),
+            ["circular schema reference '#'"]
+        );
+        // The cycle is reported inside the subschema, which then does not match
+        let schema =
+            json!({"$defs": {"a": {"anyOf": [{"$ref": "#/$defs/a"}]}}, "$ref": "#/$defs/a"});
+        assert_eq!(
+            violations(json!(1), schema),
+            ["does not match any of the allowed schemas"]
+        );
+    }
+
+    /// Returns a tool offering a function with a parameters schema
+    fn tool(name: &str, parameters: Value) -> Tool {
+        Tool::Function {
+            name: name.to_string(),
+            description: None,
+            parameters: Some(parameters),
+        }
+    }
+
+    /// Returns calls to functions with arguments
+    fn calls(calls: &[(&str, Value)]) -> Vec<ToolCall> {
+        calls
+            .iter()
+            .map(|(name, arguments)| {
+                ToolCall::function(FunctionData::new(*name, arguments.clone()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn tool_calls() {
+        let tools = [
+            tool("weather", json!({"required": ["city"]})),
+            tool("time", json!({"properties": {"zone": {"type": "string"}}})),
+        ];
+        let found = validate_tool_calls(
+            &calls(&[
+                ("weather", json!({"city": "Paris"})),
+                ("time", json!({"zone": 1})),
+                ("news", json!({})),
+            ]),
+            &tools,
+        );
+        let found: Vec<String> = found.iter().map(Violation::to_string).collect();
+        assert_eq!(
+            found,
+            [
+                "tool_calls/1/function/arguments/zone: expected string, got integer",
+                "tool_calls/2/function/name: unknown function 'news', expected one of: weather, time",
+            ]
+        );
+    }
+
+    #[test]
+    fn tool_call_with_circular_parameters() {
+        let tools = [tool(
+            "loop",
+            json!({
+                "$defs": {"a": {"$ref": "#/$defs/b"}, "b": {"$ref": "#/$defs/a"}},
+                "$ref": "#/$defs/a"
+            }),
+        )];
+        assert_eq!(
+            validate_tool_calls(&calls(&[("loop", json!({"x": 1}))]), &tools),
+            [Violation::new(
+                "tool_calls/0/function/arguments",
+                "circular schema reference '#/$defs/a'"
+            )]
+        );
+    }
+}